@@ -0,0 +1,89 @@
+// The compiler as a library. `compile` runs the same pipeline as the command line on a program given
+// as text and hands back everything it produced instead of printing it:
+//
+//     let mut options = CompileOptions::default();
+//     options.target = Target::Classic;
+//     let compiled = marie_compiler::compile(text, &options)?;
+//     print!("{}", compiled.assembly());
+//     let main = compiled.symbols()["main"];
+//
+// Diagnostics come back as values, with the warnings of a successful compile kept on the result.
+// `CompileOptions` may gain options, so it is made from its default and then changed.
+
+use std::collections::HashMap;
+
+use crate::diagnostic::Diagnostic;
+use crate::native::{self, Image};
+use crate::passes::{Level, PassManager};
+use crate::registry::Registry;
+use crate::target::Target;
+use crate::{back_end, front_end, modules, Program};
+
+#[derive(Default)]
+#[non_exhaustive]
+pub struct CompileOptions {
+    // Where the program is taken to be, for its includes. Doesn't have to exist.
+    pub path: String,
+    pub level: Level,
+    // Passes left out of the level's pipeline: dce, redundant, peephole or unused.
+    pub disabled_passes: Vec<String>,
+    // Leave out asserts.
+    pub release: bool,
+    pub target: Target,
+    pub registry: Registry,
+}
+
+pub struct CompiledProgram {
+    program: Program,
+    lines: Vec<native::Line>,
+    image: Image,
+    warnings: Vec<Diagnostic>,
+}
+
+impl CompiledProgram {
+    // The MARIE assembly, as the compile command prints it.
+    pub fn assembly(&self) -> String {
+        self.program.to_string()
+    }
+
+    // The assembly one line per memory word, from address 0.
+    pub fn lines(&self) -> &[native::Line] {
+        &self.lines
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    // Address of every label and data word.
+    pub fn symbols(&self) -> &HashMap<String, u16> {
+        &self.image.symbols
+    }
+
+    // The optimised program before assembly: its instructions with their source lines, and its data.
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+}
+
+pub fn compile(source: &str, options: &CompileOptions) -> Result<CompiledProgram, Vec<Diagnostic>> {
+    let source = modules::load_str(&options.path, source)?;
+    let place = |errors: Vec<Diagnostic>| errors.into_iter().map(|e| source.place(e)).collect::<Vec<_>>();
+
+    for name in options.disabled_passes.iter() {
+        if !crate::passes::PASS_NAMES.contains(&name.as_str()) {
+            return Err(vec![Diagnostic::error(format!("unknown pass `{}`", name))]);
+        }
+    }
+    let passes = PassManager { level: options.level, disabled: options.disabled_passes.clone(), ..PassManager::default() };
+
+    let (mut program, code) = front_end(&source, &options.registry, options.release)?;
+    let (_, warnings) = back_end(&code, &mut program, &passes, options.target).map_err(place)?;
+    let lines = program.native();
+    let image = native::assemble(&lines).map_err(place)?;
+    Ok(CompiledProgram { program, lines, image, warnings: place(warnings) })
+}
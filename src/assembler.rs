@@ -0,0 +1,220 @@
+// Assembler for MARIE assembly text. Each line is `[label,] mnemonic [operand]` with `/` starting a
+// comment; mnemonics and directives are case-insensitive. Instruction operands are labels, or hex
+// addresses when they start with a digit. `ORG` moves the location counter, `END` stops assembly, and
+// `DEC`/`HEX`/`OCT` place a data word.
+
+use crate::diagnostic::Diagnostic;
+use crate::native::{self, Image, Line, LineKind, NativeInstruction, Operand, MEMORY_SIZE};
+
+// The lines of the program with the address each one goes to.
+pub fn parse(source: &[String]) -> Result<Vec<(usize, Line)>, Vec<Diagnostic>> {
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    let mut address = 0;
+
+    for (i, text) in source.iter().enumerate() {
+        let number = i + 1;
+        let text = text.split('/').next().unwrap_or_default().trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let (label, rest) = match text.split_once(',') {
+            Some((label, rest)) => (Some(label.trim()), rest.trim()),
+            None => (None, text),
+        };
+        if let Some(label) = label {
+            if !label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                errors.push(Diagnostic::error(format!("invalid label `{}`", label)).at(number));
+                continue;
+            }
+        }
+
+        let tokens: Vec<&str> = rest.split_whitespace().collect();
+        let Some((&mnemonic, operands)) = tokens.split_first() else {
+            errors.push(Diagnostic::error("label without an instruction").at(number));
+            continue;
+        };
+        let operand = match operands {
+            [] => None,
+            [operand] => Some(*operand),
+            _ => {
+                errors.push(Diagnostic::error(format!("`{}` takes at most one operand", mnemonic)).at(number));
+                continue;
+            }
+        };
+
+        let directive = mnemonic.to_ascii_uppercase();
+        match directive.as_str() {
+            "ORG" => {
+                if label.is_some() {
+                    errors.push(Diagnostic::error("ORG can't have a label").at(number));
+                }
+                match operand.map(|o| usize::from_str_radix(o, 16)) {
+                    Some(Ok(origin)) if origin < MEMORY_SIZE => address = origin,
+                    Some(Ok(origin)) => errors.push(
+                        Diagnostic::error(format!("ORG {:X} is outside memory, which ends at {:X}", origin, MEMORY_SIZE - 1))
+                            .at(number),
+                    ),
+                    _ => errors.push(Diagnostic::error("ORG takes a hex address").at(number)),
+                }
+                continue;
+            }
+            "END" => break,
+            _ => {}
+        }
+
+        let kind = match directive.as_str() {
+            "DEC" | "HEX" | "OCT" => {
+                let Some(operand) = operand else {
+                    errors.push(Diagnostic::error(format!("{} takes a value", directive)).at(number));
+                    continue;
+                };
+                match data(&directive, operand) {
+                    Some(kind) => kind,
+                    None => {
+                        errors.push(Diagnostic::error(format!("`{}` is not a 16-bit {} value", operand, directive)).at(number));
+                        continue;
+                    }
+                }
+            }
+            _ => {
+                let Some(instruction) = NativeInstruction::from_str(mnemonic) else {
+                    errors.push(Diagnostic::error(format!("unknown instruction `{}`", mnemonic)).at(number));
+                    continue;
+                };
+                match (instruction.has_operand(), operand) {
+                    (true, Some(operand)) => match instruction_operand(operand) {
+                        Some(operand) => LineKind::Instruction(instruction, Some(operand)),
+                        None => {
+                            errors.push(Diagnostic::error(format!("invalid operand `{}`", operand)).at(number));
+                            continue;
+                        }
+                    },
+                    (false, None) => LineKind::Instruction(instruction, None),
+                    (true, None) => {
+                        errors.push(Diagnostic::error(format!("`{}` takes an operand", instruction)).at(number));
+                        continue;
+                    }
+                    (false, Some(_)) => {
+                        errors.push(Diagnostic::error(format!("`{}` takes no operand", instruction)).at(number));
+                        continue;
+                    }
+                }
+            }
+        };
+
+        lines.push((address, Line { label: label.map(String::from), kind, source: Some(number) }));
+        address += 1;
+    }
+
+    if errors.is_empty() {
+        Ok(lines)
+    }
+    else {
+        Err(errors)
+    }
+}
+
+fn data(directive: &str, operand: &str) -> Option<LineKind> {
+    match directive {
+        "DEC" => operand.parse::<i16>().ok().map(LineKind::Dec),
+        "HEX" => u16::from_str_radix(operand, 16).ok().map(LineKind::Hex),
+        _ => u16::from_str_radix(operand, 8).ok().map(LineKind::Hex),
+    }
+}
+
+fn instruction_operand(operand: &str) -> Option<Operand> {
+    if operand.starts_with(|c: char| c.is_ascii_digit()) {
+        u16::from_str_radix(operand, 16).ok().map(Operand::Number)
+    }
+    else if operand.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Some(Operand::Symbol(operand.to_string()))
+    }
+    else {
+        None
+    }
+}
+
+// Parses and resolves symbols, reporting undefined and duplicate labels with their line numbers.
+pub fn assemble(source: &[String]) -> Result<Image, Vec<Diagnostic>> {
+    let lines = parse(source)?;
+    let placed: Vec<(usize, &Line)> = lines.iter().map(|(address, line)| (*address, line)).collect();
+    native::place(&placed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    fn words(text: &str) -> Vec<u16> {
+        assemble(&source(text)).unwrap_or_else(|errors| panic!("{:?}", errors)).words
+    }
+
+    // The first error's message and line.
+    fn error(text: &str) -> (String, Option<usize>) {
+        let errors = assemble(&source(text)).unwrap_err();
+        (errors[0].message.clone(), errors[0].line)
+    }
+
+    #[test]
+    fn instructions_and_labels() {
+        let image = assemble(&source("  Load x / comment\nstart, output\nJUMP start\nx, dec 5")).unwrap();
+        assert_eq!(image.words, [0x1003, 0x6000, 0x9001, 5]);
+        assert_eq!(image.symbols["start"], 1);
+        assert_eq!(image.lines, [Some(1), Some(2), Some(3), Some(4)]);
+    }
+
+    #[test]
+    fn org_moves_the_location_counter() {
+        let image = assemble(&source("org 100\nhalt\nx, hex 2A")).unwrap();
+        assert_eq!(image.words.len(), 0x102);
+        assert_eq!(image.words[0x100..], [0x7000, 0x2A]);
+        assert_eq!(image.symbols["x"], 0x101);
+        assert!(image.words[..0x100].iter().all(|&w| w == 0));
+
+        assert!(error("org 1000").0.contains("outside memory"));
+        assert_eq!(error("org xyz").0, "ORG takes a hex address");
+        assert_eq!(error("a, org 10").0, "ORG can't have a label");
+        assert!(error("org 10\nhalt\norg 10\nhalt").0.contains("already taken"));
+    }
+
+    #[test]
+    fn end_stops_assembly() {
+        assert_eq!(words("halt\nend\nthis is not assembly"), [0x7000]);
+    }
+
+    #[test]
+    fn data_directives() {
+        assert_eq!(words("dec -1\ndec 32767\nhex FFFF\noct 17"), [0xFFFF, 0x7FFF, 0xFFFF, 0o17]);
+        assert_eq!(error("halt\ndec 32768"), ("`32768` is not a 16-bit DEC value".to_string(), Some(2)));
+        assert_eq!(error("hex 10000").0, "`10000` is not a 16-bit HEX value");
+        assert_eq!(error("oct 8").0, "`8` is not a 16-bit OCT value");
+        assert_eq!(error("dec").0, "DEC takes a value");
+    }
+
+    #[test]
+    fn label_errors() {
+        assert_eq!(error("a, halt\nload b\na, dec 1"), ("label `a` defined twice".to_string(), Some(3)));
+        assert_eq!(error("halt\nload nowhere"), ("undefined label `nowhere`".to_string(), Some(2)));
+        assert_eq!(error("1a, halt").0, "invalid label `1a`");
+        assert_eq!(error("a,").0, "label without an instruction");
+    }
+
+    #[test]
+    fn operand_errors() {
+        assert_eq!(words("load 0FFF\nskipcond 800"), [0x1FFF, 0x8800]);
+        assert!(error("load 1000").0.contains("out of range"));
+        assert_eq!(error("load a-b").0, "invalid operand `a-b`");
+        assert_eq!(error("load").0, "`load` takes an operand");
+        assert_eq!(error("halt 1").0, "`halt` takes no operand");
+        assert_eq!(error("load a b").0, "`load` takes at most one operand");
+        assert_eq!(error("frob x").0, "unknown instruction `frob`");
+    }
+}
@@ -0,0 +1,402 @@
+// Syntax tree of a source file: one statement per line, with every operand already classified by its
+// sigil ($ value, @ pointer, & address, # label). Names can be qualified by a module, as in `$math.tmp`.
+
+use std::fmt;
+
+use regex::Regex;
+
+use crate::diagnostic::Diagnostic;
+use crate::native::NativeInstruction;
+use crate::validate;
+
+const NUM_LIT: &str = r#"\$\d[\d_]*"#;
+const DIR_LIT: &str = r#"\$[A-Za-z]\w*(\.[A-Za-z]\w*)?"#;
+const PTR_LIT: &str = r#"@[A-Za-z]\w*(\.[A-Za-z]\w*)?"#;
+const ADR_LIT: &str = r#"\&[A-Za-z]\w*(\.[A-Za-z]\w*)?"#;
+const OFF_LIT: &str = r#"@[A-Za-z]\w*(\.[A-Za-z]\w*)?\[\$[A-Za-z]\w*(\.[A-Za-z]\w*)?\]"#;
+const OFF_NUM_LIT: &str = r#"@[A-Za-z]\w*(\.[A-Za-z]\w*)?\[\$\d[\d_]*\]"#;
+const ELEM_LIT: &str = r#"\&[A-Za-z]\w*(\.[A-Za-z]\w*)?\[\$[A-Za-z]\w*(\.[A-Za-z]\w*)?\]"#;
+const ELEM_NUM_LIT: &str = r#"\&[A-Za-z]\w*(\.[A-Za-z]\w*)?\[\$\d[\d_]*\]"#;
+const CHAIN_LIT: &str = r#"@@+[A-Za-z]\w*(\.[A-Za-z]\w*)?"#;
+const CELL_LIT: &str = r#"\$[A-Za-z]\w*(\.[A-Za-z]\w*)?(\[\$([A-Za-z]\w*(\.[A-Za-z]\w*)?|\d[\d_]*)\]){2}"#;
+const ARRAY_LIT: &str = r#"var \$[A-Za-z]\w*(\[\$\d[\d_]*\]){1,2}"#;
+const LABEL_LIT: &str = r#"#[A-Za-z]\w*(\.[A-Za-z]\w*)?"#;
+const MODULE_LIT: &str = r#"module [A-Za-z]\w*"#;
+const INCLUDE_LIT: &str = r#"include "[^"]+""#;
+const MACRO_LIT: &str = r#"macro [A-Za-z]\w* *\(([^)]*)\) *\{"#;
+const PARAM_LIT: &str = r#"[$#][A-Za-z]\w*"#;
+const ASM_LIT: &str = r#"asm *\{"#;
+const ASM_NAME_LIT: &str = r#"[A-Za-z]\w*(\.[A-Za-z]\w*)?"#;
+const ASM_HEX_LIT: &str = r#"\d[0-9A-Fa-f]*"#;
+const CODE_LIT: &str = r#"[0-9A-Fa-f]{3}"#;
+const REL_LIT: &str = r#"==|!=|<=|>=|<|>"#;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Number(i16),                 // $5
+    Var(String),                 // $x
+    Pointer(String),             // @p
+    Address(String),             // &x
+    Index(String, Box<Operand>), // @p[$i] or @p[$3]
+    Element(String, Box<Operand>), // &x[$i] or &x[$3], the address `$i` words past x
+    Indirect(String, usize),       // @@pp, the pointer followed that many times
+    Cell(String, Box<Operand>, Box<Operand>), // $grid[$r][$c] of a two-dimensional array
+    Deref(Box<Operand>),                      // @(&x[$i]), the word at the address another operand gives
+    Label(String),               // #loop
+    Code(String),                // 800, the condition of a skipcond or an address in an asm block
+    Relation(String),            // >, the comparison in an assert
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Number(n) => write!(f, "${}", n),
+            Operand::Var(name) => write!(f, "${}", name),
+            Operand::Pointer(name) => write!(f, "@{}", name),
+            Operand::Address(name) => write!(f, "&{}", name),
+            Operand::Index(name, offset) => write!(f, "@{}[{}]", name, offset),
+            Operand::Element(name, offset) => write!(f, "&{}[{}]", name, offset),
+            Operand::Indirect(name, depth) => write!(f, "{}{}", "@".repeat(*depth), name),
+            Operand::Cell(name, row, column) => write!(f, "${}[{}][{}]", name, row, column),
+            Operand::Deref(address) => write!(f, "@({})", address),
+            Operand::Label(name) => write!(f, "#{}", name),
+            Operand::Code(code) => write!(f, "{}", code),
+            Operand::Relation(relation) => write!(f, "{}", relation),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StatementKind {
+    Var { name: String, value: Option<i16> },
+    // `var $buf[$8]` or `var $grid[$3][$4]`, rows first.
+    Array { name: String, dims: Vec<u16> },
+    Const { name: String, value: i16 },
+    Label(String),
+    Instruction { mnemonic: String, operands: Vec<Operand> },
+    // `$d = $a`, or `$d = $a + $b` and `$d = $a - $b` with the operator.
+    Assign { dest: Operand, lhs: Operand, rhs: Option<(char, Operand)> },
+    Include(String),
+    Module(String),
+    // `macro name($a, #b) {` up to the `}` that ends it.
+    Macro { name: String, params: Vec<String> },
+    EndMacro,
+    // A line of an `asm { ... }` block, passed through as it is.
+    Asm { label: Option<String>, instruction: NativeInstruction, operand: Option<Operand> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub line: usize,
+    pub kind: StatementKind,
+    // Marked `pub`, so other modules can use what it declares.
+    pub public: bool,
+}
+
+impl Statement {
+    // The name a declaration, label or subroutine introduces.
+    pub fn declares(&self) -> Option<&str> {
+        match &self.kind {
+            StatementKind::Var { name, .. } | StatementKind::Const { name, .. } | StatementKind::Label(name) => Some(name),
+            StatementKind::Array { name, .. } => Some(name),
+            StatementKind::Asm { label: Some(name), .. } => Some(name),
+            StatementKind::Instruction { mnemonic, operands } if mnemonic == "sub" => match operands.as_slice() {
+                [Operand::Label(name)] => Some(name),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+pub fn valid_name(name: &str) -> bool {
+    validate(r#"[A-Za-z]\w*"#, name)
+}
+
+fn number(token: &str) -> Result<i16, String> {
+    token[1..]
+        .replace('_', "")
+        .parse::<i16>()
+        .map_err(|_| format!("`{}` doesn't fit in a 16-bit word", token))
+}
+
+pub fn parse_operand(token: &str) -> Result<Operand, String> {
+    if validate(NUM_LIT, token) {
+        Ok(Operand::Number(number(token)?))
+    }
+    else if validate(DIR_LIT, token) {
+        Ok(Operand::Var(token[1..].to_string()))
+    }
+    else if validate(PTR_LIT, token) {
+        Ok(Operand::Pointer(token[1..].to_string()))
+    }
+    else if validate(ADR_LIT, token) {
+        Ok(Operand::Address(token[1..].to_string()))
+    }
+    else if validate(OFF_LIT, token) || validate(OFF_NUM_LIT, token) {
+        let open = token.find('[').unwrap();
+        let offset = parse_operand(&token[open + 1..token.len() - 1])?;
+        Ok(Operand::Index(token[1..open].to_string(), Box::new(offset)))
+    }
+    else if validate(ELEM_LIT, token) || validate(ELEM_NUM_LIT, token) {
+        let open = token.find('[').unwrap();
+        let offset = parse_operand(&token[open + 1..token.len() - 1])?;
+        Ok(Operand::Element(token[1..open].to_string(), Box::new(offset)))
+    }
+    else if validate(CHAIN_LIT, token) {
+        let name = token.trim_start_matches('@');
+        Ok(Operand::Indirect(name.to_string(), token.len() - name.len()))
+    }
+    else if validate(CELL_LIT, token) {
+        let open = token.find('[').unwrap();
+        let middle = token.find("][").unwrap();
+        let row = parse_operand(&token[open + 1..middle])?;
+        let column = parse_operand(&token[middle + 2..token.len() - 1])?;
+        Ok(Operand::Cell(token[1..open].to_string(), Box::new(row), Box::new(column)))
+    }
+    else if token.starts_with("@(") && token.ends_with(')') {
+        Ok(Operand::Deref(Box::new(parse_operand(&token[2..token.len() - 1])?)))
+    }
+    else if validate(LABEL_LIT, token) {
+        Ok(Operand::Label(token[1..].to_string()))
+    }
+    else if validate(CODE_LIT, token) {
+        Ok(Operand::Code(token.to_string()))
+    }
+    else if validate(REL_LIT, token) {
+        Ok(Operand::Relation(token.to_string()))
+    }
+    else {
+        Err(format!("invalid operand `{}`", token))
+    }
+}
+
+fn remove_comment(line: &str) -> &str {
+    match line.find("//") {
+        Some(start) => &line[..start],
+        None => line,
+    }
+}
+
+fn parse_declaration(keyword: &str, text: &str) -> Result<(String, Option<i16>), String> {
+    let no_default_re = format!(r#"{} {}"#, keyword, DIR_LIT);
+    let default_re = format!(r#"{} {} *= *{}"#, keyword, DIR_LIT, NUM_LIT);
+
+    let var_re = Regex::new(DIR_LIT).unwrap();
+    let name = var_re.find(text).map(|m| m.as_str()[1..].to_string());
+
+    if validate(&no_default_re, text) {
+        Ok((name.unwrap(), None))
+    }
+    else if validate(&default_re, text) {
+        let num_lit_re = Regex::new(NUM_LIT).unwrap();
+        let value = number(num_lit_re.find(text).unwrap().as_str())?;
+        Ok((name.unwrap(), Some(value)))
+    }
+    else {
+        Err(format!("malformed declaration `{}`", text))
+    }
+}
+
+fn parse_array(text: &str) -> Result<StatementKind, String> {
+    let open = text.find('[').unwrap();
+    let name = text[5..open].to_string();
+    let mut dims = Vec::new();
+    for dim in text[open + 1..text.len() - 1].split("][") {
+        match number(dim)? {
+            n if n > 0 => dims.push(n as u16),
+            _ => return Err(format!("array `{}` needs a size of at least one", name)),
+        }
+    }
+    if array_size(&dims).is_none() {
+        return Err(format!("array `{}` is too large", name));
+    }
+    Ok(StatementKind::Array { name, dims })
+}
+
+// Words an array with these dimensions takes, none when that is more than memory holds.
+pub fn array_size(dims: &[u16]) -> Option<u16> {
+    dims.iter().try_fold(1u16, |size, &dim| size.checked_mul(dim)).filter(|&size| size <= 4096)
+}
+
+fn parse_macro(text: &str) -> Result<StatementKind, String> {
+    if !validate(MACRO_LIT, text) {
+        return Err(format!("malformed macro `{}`, expected `macro name($a, $b) {{`", text));
+    }
+    let open = text.find('(').unwrap();
+    let close = text.find(')').unwrap();
+    let name = text[6..open].trim().to_string();
+
+    let mut params = Vec::new();
+    for param in text[open + 1..close].split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        if !validate(PARAM_LIT, param) {
+            return Err(format!("invalid macro parameter `{}`", param));
+        }
+        if params.iter().any(|p| p == &param[1..]) {
+            return Err(format!("macro parameter `{}` appears twice", param));
+        }
+        params.push(param[1..].to_string());
+    }
+    Ok(StatementKind::Macro { name, params })
+}
+
+// `label, mnemonic operand` in MARIE's own syntax, where the operand is a `$variable` or `$N` of the
+// program, a label, or a hex address starting with a digit. Comments start with `/` or `//`.
+fn parse_asm_line(line: &str) -> Result<Option<StatementKind>, String> {
+    let text = line.split('/').next().unwrap_or_default().trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    let (label, text) = match text.split_once(',') {
+        Some((label, rest)) if valid_name(label.trim()) => (Some(label.trim().to_string()), rest.trim()),
+        Some((label, _)) => return Err(format!("invalid label `{}`", label.trim())),
+        None => (None, text),
+    };
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let Some(&mnemonic) = tokens.first() else {
+        return Ok(label.map(StatementKind::Label));
+    };
+    let instruction = NativeInstruction::from_str(mnemonic).ok_or(format!("unknown MARIE instruction `{}`", mnemonic))?;
+
+    let operand = match (instruction.has_operand(), &tokens[1..]) {
+        (true, [token]) => Some(parse_asm_operand(token)?),
+        (false, []) => None,
+        (true, _) => return Err(format!("`{}` takes one operand", instruction)),
+        (false, _) => return Err(format!("`{}` takes no operand", instruction)),
+    };
+    Ok(Some(StatementKind::Asm { label, instruction, operand }))
+}
+
+fn parse_asm_operand(token: &str) -> Result<Operand, String> {
+    if validate(NUM_LIT, token) || validate(DIR_LIT, token) {
+        parse_operand(token)
+    }
+    else if validate(ASM_NAME_LIT, token) {
+        Ok(Operand::Label(token.to_string()))
+    }
+    else if validate(ASM_HEX_LIT, token) {
+        match u16::from_str_radix(token, 16) {
+            Ok(address) if address <= 0xFFF => Ok(Operand::Code(format!("{:03X}", address))),
+            _ => Err(format!("`{}` doesn't fit in an address", token)),
+        }
+    }
+    else {
+        Err(format!("invalid operand `{}`, expected `$name`, a label or a hex address", token))
+    }
+}
+
+fn parse_assignment(tokens: &[&str]) -> Result<StatementKind, String> {
+    let (dest, lhs) = match tokens {
+        [dest, _, lhs] | [dest, _, lhs, _, _] => (parse_operand(dest)?, parse_operand(lhs)?),
+        _ => return Err(format!("malformed assignment `{}`, expected `$d = $a` or `$d = $a + $b`", tokens.join(" "))),
+    };
+    let rhs = match tokens {
+        [_, _, _, op @ ("+" | "-"), rhs] => Some((op.chars().next().unwrap(), parse_operand(rhs)?)),
+        [_, _, _, op, _] => return Err(format!("unknown operator `{}`, expected `+` or `-`", op)),
+        _ => None,
+    };
+    Ok(StatementKind::Assign { dest, lhs, rhs })
+}
+
+fn parse_line(line: &str) -> Result<Option<StatementKind>, String> {
+    let text = remove_comment(line).trim();
+    let tokens: Vec<&str> = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty())
+        .collect();
+
+    let Some(&first) = tokens.first() else {
+        return Ok(None);
+    };
+
+    let kind = match first {
+        "include" if validate(INCLUDE_LIT, text) => StatementKind::Include(text[9..text.len() - 1].to_string()),
+        "include" => return Err(format!("malformed include `{}`, expected `include \"path\"`", text)),
+        "module" if validate(MODULE_LIT, text) => StatementKind::Module(tokens[1].to_string()),
+        "module" => return Err(format!("malformed module name `{}`", text)),
+        "macro" => parse_macro(text)?,
+        "}" if tokens.len() == 1 => StatementKind::EndMacro,
+        "var" if validate(ARRAY_LIT, text) => parse_array(text)?,
+        "var" => {
+            let (name, value) = parse_declaration("var", text)?;
+            StatementKind::Var { name, value }
+        }
+        "const" => match parse_declaration("const", text)? {
+            (name, Some(value)) => StatementKind::Const { name, value },
+            (name, None) => return Err(format!("constant `{}` needs a value", name)),
+        },
+        _ if tokens.get(1) == Some(&"=") => parse_assignment(&tokens)?,
+        _ if first.starts_with('#') => {
+            if tokens.len() > 1 || !validate(LABEL_LIT, first) {
+                return Err(format!("malformed label `{}`", text));
+            }
+            StatementKind::Label(first[1..].to_string())
+        }
+        _ => StatementKind::Instruction {
+            mnemonic: first.to_string(),
+            operands: tokens[1..].iter().map(|t| parse_operand(t)).collect::<Result<_, _>>()?,
+        },
+    };
+
+    Ok(Some(kind))
+}
+
+// Parses every line, collecting all the errors rather than stopping at the first one.
+pub fn parse(lines: &[String]) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+
+    // The line an open `asm {` is on.
+    let mut asm = None;
+
+    for (number, line) in lines.iter().enumerate() {
+        if asm.is_some() {
+            let result = match line.split('/').next().unwrap_or_default().trim() {
+                "}" => {
+                    asm = None;
+                    Ok(None)
+                }
+                _ => parse_asm_line(line),
+            };
+            match result {
+                Ok(Some(kind)) => statements.push(Statement { line: number + 1, kind, public: false }),
+                Ok(None) => {}
+                Err(message) => errors.push(Diagnostic::error(message).at(number + 1)),
+            }
+            continue;
+        }
+        if validate(ASM_LIT, remove_comment(line).trim()) {
+            asm = Some(number + 1);
+            continue;
+        }
+
+        let (public, text) = match line.trim_start().strip_prefix("pub ") {
+            Some(rest) => (true, rest),
+            None => (false, line.as_str()),
+        };
+        match parse_line(text) {
+            Ok(Some(kind)) => {
+                let stmt = Statement { line: number + 1, kind, public };
+                if public && stmt.declares().is_none() {
+                    errors.push(Diagnostic::error("only declarations, labels and subroutines can be `pub`").at(number + 1));
+                }
+                statements.push(stmt);
+            }
+            Ok(None) if public => errors.push(Diagnostic::error("`pub` needs a declaration").at(number + 1)),
+            Ok(None) => {}
+            Err(message) => errors.push(Diagnostic::error(message).at(number + 1)),
+        }
+    }
+
+    if let Some(line) = asm {
+        errors.push(Diagnostic::error("`asm` block has no closing `}`").at(line));
+    }
+
+    if errors.is_empty() {
+        Ok(statements)
+    }
+    else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,303 @@
+// Control-flow graph over the generated instructions. A basic block is a run of instructions that is
+// only entered at its first one and only left after its last one; blocks start at labels and after
+// every jump, skipcond and jns.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::native::{LineKind, NativeInstruction as NI, Operand};
+use crate::{Label, Program, SimpleInstruction};
+
+// What an instruction does to control flow. Asm lines act as the MARIE instructions they are; a jump
+// to a numeric address goes nowhere the graph knows.
+enum Flow<'a> {
+    Next,
+    Label,
+    Jump(Option<&'a str>),
+    Skip,
+    Call(Option<&'a str>),
+    Stop,
+}
+
+fn flow(instruction: &SimpleInstruction) -> Flow<'_> {
+    use SimpleInstruction as SI;
+    fn symbol(operand: &Option<Operand>) -> Option<&str> {
+        match operand {
+            Some(Operand::Symbol(name)) => Some(name),
+            _ => None,
+        }
+    }
+    match instruction {
+        SI::Label(_) => Flow::Label,
+        SI::Jump(label) => Flow::Jump(Some(label)),
+        SI::Skipcond(_) => Flow::Skip,
+        SI::Jns(label) => Flow::Call(Some(label)),
+        SI::JumpI(_) | SI::Halt => Flow::Stop,
+        SI::Asm(LineKind::Instruction(NI::Jump, operand)) => Flow::Jump(symbol(operand)),
+        SI::Asm(LineKind::Instruction(NI::Skipcond, _)) => Flow::Skip,
+        SI::Asm(LineKind::Instruction(NI::Jns, operand)) => Flow::Call(symbol(operand)),
+        SI::Asm(LineKind::Instruction(NI::Jumpi | NI::Halt, _)) => Flow::Stop,
+        _ => Flow::Next,
+    }
+}
+
+// The instruction a skipcond skips: the next one that isn't a label, labels take no memory.
+fn skipped(instructions: &[SimpleInstruction], skipcond: usize) -> Option<usize> {
+    (skipcond + 1..instructions.len()).find(|&k| !matches!(instructions[k], SimpleInstruction::Label(_)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    // Skipcond found its condition true and skipped the next instruction.
+    Skip,
+    // Jns entered a subroutine.
+    Call,
+    // The subroutine called by jns returned to the instruction after it.
+    Return,
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub range: Range<usize>,
+    pub label: Option<Label>,
+    pub successors: Vec<Edge>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    pub entry: Option<usize>,
+}
+
+impl Cfg {
+    pub fn build(instructions: &[SimpleInstruction]) -> Cfg {
+        use SimpleInstruction as SI;
+        let mut leaders = vec![0];
+
+        for (i, instruction) in instructions.iter().enumerate() {
+            match flow(instruction) {
+                Flow::Label => leaders.push(i),
+                Flow::Jump(_) | Flow::Stop | Flow::Call(_) => leaders.push(i + 1),
+                // The instruction after a skipcond may be skipped, so both it and the one after start a block.
+                Flow::Skip => leaders.extend([Some(i + 1), skipped(instructions, i).map(|k| k + 1)].into_iter().flatten()),
+                Flow::Next => {}
+            }
+        }
+
+        leaders.retain(|&l| l < instructions.len());
+        leaders.sort();
+        leaders.dedup();
+
+        let mut blocks: Vec<BasicBlock> = leaders
+            .iter()
+            .enumerate()
+            .map(|(k, &start)| BasicBlock {
+                range: start..leaders.get(k + 1).copied().unwrap_or(instructions.len()),
+                label: match &instructions[start] {
+                    SI::Label(label) => Some(label.clone()),
+                    _ => None,
+                },
+                successors: Vec::new(),
+            })
+            .collect();
+
+        let block_at: HashMap<usize, usize> = blocks.iter().enumerate().map(|(k, b)| (b.range.start, k)).collect();
+        let labelled: HashMap<Label, usize> = blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(k, b)| b.label.clone().map(|l| (l, k)))
+            .collect();
+
+        for block in blocks.iter_mut() {
+            let end = block.range.end;
+            let edge = |at: usize, kind: EdgeKind| block_at.get(&at).map(|&to| Edge { to, kind });
+
+            block.successors = match flow(&instructions[end - 1]) {
+                Flow::Jump(label) => label
+                    .and_then(|l| labelled.get(l))
+                    .map(|&to| Edge { to, kind: EdgeKind::Jump })
+                    .into_iter()
+                    .collect(),
+                // Jumpi returns from a subroutine, the caller's side of the graph has the return edge.
+                Flow::Stop => Vec::new(),
+                Flow::Skip => [edge(end, EdgeKind::Fallthrough), skipped(instructions, end - 1).and_then(|k| edge(k + 1, EdgeKind::Skip))]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+                Flow::Call(label) => label
+                    .and_then(|l| labelled.get(l))
+                    .map(|&to| Edge { to, kind: EdgeKind::Call })
+                    .into_iter()
+                    .chain(edge(end, EdgeKind::Return))
+                    .collect(),
+                Flow::Label | Flow::Next => edge(end, EdgeKind::Fallthrough).into_iter().collect(),
+            };
+        }
+
+        // The program is entered through `main`, or at the top when there is no such label.
+        let entry = labelled.get("main").copied().or(if blocks.is_empty() { None } else { Some(0) });
+
+        Cfg { blocks, entry }
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<usize>> {
+        let mut result = vec![Vec::new(); self.blocks.len()];
+        for (k, block) in self.blocks.iter().enumerate() {
+            for edge in block.successors.iter() {
+                result[edge.to].push(k);
+            }
+        }
+        result
+    }
+
+    // Blocks some path from one of the roots reaches.
+    pub fn reachable(&self, roots: &[usize]) -> Vec<bool> {
+        let mut seen = vec![false; self.blocks.len()];
+        let mut work = roots.to_vec();
+
+        while let Some(k) = work.pop() {
+            if seen[k] {
+                continue;
+            }
+            seen[k] = true;
+            work.extend(self.blocks[k].successors.iter().map(|e| e.to));
+        }
+
+        seen
+    }
+
+    // Graphviz rendering, one box per block listing its MARIE instructions.
+    pub fn to_dot(&self, prog: &Program) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        for (k, block) in self.blocks.iter().enumerate() {
+            let mut text = String::new();
+            for instruction in prog.instructions[block.range.clone()].iter() {
+                for line in instruction.to_native().lines() {
+                    text.push_str(&escape(line));
+                    text.push_str("\\l");
+                }
+            }
+
+            let shape = if Some(k) == self.entry { ", peripheries=2" } else { "" };
+            let _ = writeln!(dot, "    b{} [label=\"{}\"{}];", k, text, shape);
+        }
+
+        for (k, block) in self.blocks.iter().enumerate() {
+            for edge in block.successors.iter() {
+                let attributes = match edge.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                    EdgeKind::Skip => " [label=\"skip\", style=dashed]",
+                    EdgeKind::Call => " [label=\"call\"]",
+                    EdgeKind::Return => " [label=\"return\", style=dotted]",
+                };
+                let _ = writeln!(dot, "    b{} -> b{}{};", k, edge.to, attributes);
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SkipcondType;
+
+    use SimpleInstruction as SI;
+
+    fn label(name: &str) -> SI {
+        SI::Label(name.to_string())
+    }
+
+    fn asm(instruction: NI, operand: Option<Operand>) -> SI {
+        SI::Asm(LineKind::Instruction(instruction, operand))
+    }
+
+    // Edges as the instruction indices their blocks start at.
+    fn edges(instructions: &[SI]) -> Vec<(usize, usize, EdgeKind)> {
+        let cfg = Cfg::build(instructions);
+        let start = |k: usize| cfg.blocks[k].range.start;
+        let mut edges: Vec<_> = cfg
+            .blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(k, b)| b.successors.iter().map(move |e| (start(k), start(e.to), e.kind)))
+            .collect();
+        edges.sort_by_key(|&(from, to, _)| (from, to));
+        edges
+    }
+
+    #[test]
+    fn straight_line_code_is_one_block() {
+        let cfg = Cfg::build(&[SI::Input, SI::Output, SI::Halt]);
+        assert_eq!(cfg.blocks.len(), 1);
+        assert_eq!(cfg.entry, Some(0));
+        assert!(cfg.blocks[0].successors.is_empty());
+    }
+
+    #[test]
+    fn jumps_go_to_their_label() {
+        let code = [label("main"), SI::Jump("end".to_string()), SI::Output, label("end"), SI::Halt];
+        assert_eq!(edges(&code), [(0, 3, EdgeKind::Jump), (2, 3, EdgeKind::Fallthrough)]);
+        // `main` is the entry even when it isn't first.
+        let cfg = Cfg::build(&[SI::Output, label("main"), SI::Halt]);
+        assert_eq!(cfg.entry, Some(1));
+    }
+
+    #[test]
+    fn skipcond_falls_through_or_skips_one_instruction() {
+        let code = [SI::Input, SI::Skipcond(SkipcondType::Zero), SI::Output, SI::Clear, SI::Halt];
+        assert_eq!(edges(&code), [(0, 2, EdgeKind::Fallthrough), (0, 3, EdgeKind::Skip), (2, 3, EdgeKind::Fallthrough)]);
+    }
+
+    // Labels take no memory, so the skip lands after the instruction that follows them.
+    #[test]
+    fn skipcond_skips_past_labels() {
+        let code = [asm(NI::Skipcond, Some(Operand::Number(0x400))), label("a"), SI::Output, SI::Halt];
+        assert_eq!(edges(&code), [(0, 1, EdgeKind::Fallthrough), (0, 3, EdgeKind::Skip), (1, 3, EdgeKind::Fallthrough)]);
+    }
+
+    #[test]
+    fn jns_calls_and_returns() {
+        let code = [
+            label("main"),
+            SI::Jns("sub".to_string()),
+            SI::Halt,
+            label("sub"),
+            SI::Output,
+            SI::JumpI("sub".to_string()),
+        ];
+        assert_eq!(edges(&code), [(0, 2, EdgeKind::Return), (0, 3, EdgeKind::Call)]);
+    }
+
+    // An asm jump to an address goes nowhere the graph knows, one to a label is a normal jump.
+    #[test]
+    fn asm_jumps() {
+        let code = [asm(NI::Jump, Some(Operand::Number(0x10))), label("a"), asm(NI::Jump, Some(Operand::Symbol("a".to_string())))];
+        assert_eq!(edges(&code), [(1, 1, EdgeKind::Jump)]);
+    }
+
+    #[test]
+    fn reachable_blocks() {
+        let code = [label("main"), SI::Halt, SI::Output, label("x"), SI::Halt];
+        let cfg = Cfg::build(&code);
+        assert_eq!(cfg.reachable(&[cfg.entry.unwrap()]), [true, false, false]);
+        assert_eq!(cfg.predecessors(), [vec![], vec![], vec![1]]);
+    }
+}
@@ -0,0 +1,63 @@
+// Dead code elimination: walks the control-flow graph from the entry point and removes every block
+// no path reaches, e.g. code after `halt` or an unconditional `jump`.
+
+use std::collections::HashSet;
+
+use crate::cfg::Cfg;
+use crate::diagnostic::Diagnostic;
+use crate::{Program, SimpleInstruction};
+
+// Blocks reachable from the entry point. Any label whose address is taken stays live as well, it may be
+// jumped to through a jump table at run time, and so does any label asm code refers to.
+fn reachable(prog: &Program, cfg: &Cfg) -> Vec<bool> {
+    let asm_targets: HashSet<&str> = prog.instructions.iter().filter_map(|i| i.asm_symbol()).collect();
+    let mut roots: Vec<usize> = cfg.entry.into_iter().collect();
+    roots.extend(
+        cfg.blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| {
+                matches!(&b.label, Some(l) if prog.get_variable(&format!("{}_addr", l)).is_some() || asm_targets.contains(l.as_str()))
+            })
+            .map(|(k, _)| k),
+    );
+
+    cfg.reachable(&roots)
+}
+
+// Removes unreachable blocks and returns a warning for every stretch of source code that went with them.
+// The initialiser of a `var` declared outside the code, as in a module's header, doesn't count: its data
+// word starts out with the value anyway.
+pub fn remove_unreachable(prog: &mut Program) -> Vec<Diagnostic> {
+    let declarations: HashSet<usize> = prog.variables.iter().filter_map(|v| v.line).collect();
+    let cfg = Cfg::build(&prog.instructions);
+    let reachable = reachable(prog, &cfg);
+
+    // Merge neighbouring dead blocks so one stretch of dead code gives one warning.
+    let mut dead: Vec<std::ops::Range<usize>> = Vec::new();
+    for (block, _) in cfg.blocks.iter().zip(reachable.iter()).filter(|(_, r)| !**r) {
+        match dead.last_mut() {
+            Some(last) if last.end == block.range.start => last.end = block.range.end,
+            _ => dead.push(block.range.clone()),
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for range in dead.iter().rev() {
+        let code: Vec<usize> = range
+            .clone()
+            .filter(|&i| !matches!(prog.instructions[i], SimpleInstruction::Label(_)))
+            .map(|i| prog.lines[i])
+            .filter(|line| *line != 0 && !declarations.contains(line))
+            .collect();
+
+        if let (Some(&first), Some(&last)) = (code.iter().min(), code.iter().max()) {
+            warnings.push(Diagnostic::warning("unreachable code removed").at(first).through(last));
+        }
+
+        prog.splice(range.clone(), Vec::new());
+    }
+
+    warnings.reverse();
+    warnings
+}
@@ -0,0 +1,320 @@
+// Interactive debugger over the simulator. Breakpoints and stepping work on source lines through the
+// image's line map, and variables are looked up by their source names in the program's data words.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::modules::Source;
+use crate::native::{Image, Line, LineKind};
+use crate::simulator::{Fault, Machine, StdIo};
+use crate::Program;
+
+const HELP: &str = "\
+break <line|file:line|label>
+                     stop when execution reaches a source line or label (b)
+delete [n]           remove breakpoint n, or all of them
+watch <var|addr>     stop when a variable or the word at an address changes (w)
+unwatch <var|addr>   stop watching it
+info                 list breakpoints and watches
+continue             run to the next breakpoint, watch or halt (c, run)
+step [n]             run to the next source statement (s)
+stepi [n]            run one MARIE instruction (si)
+print <var|@ptr|addr> show a word, by variable name, through a pointer or by address (p)
+regs                 show the registers
+where                show the current source line and instruction
+quit                 leave the debugger (q)
+An empty line repeats the last command.";
+
+enum Stop {
+    Breakpoint(usize),
+    Watch(String, i16, i16),
+    Halted,
+    Fault(Fault),
+    Limit,
+    Done,
+}
+
+struct Watch {
+    name: String,
+    address: u16,
+    value: i16,
+}
+
+pub struct Debugger<'a> {
+    machine: Machine,
+    image: Image,
+    lines: Vec<Line>,
+    source: &'a Source,
+    variables: HashMap<String, u16>,
+    // The address each breakpoint stops at, with what the user asked for.
+    breakpoints: Vec<(u16, String)>,
+    watches: Vec<Watch>,
+    io: StdIo,
+    step_limit: u64,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(prog: &Program, image: Image, lines: Vec<Line>, source: &'a Source, step_limit: u64) -> Debugger<'a> {
+        Debugger {
+            machine: Machine::new(&image),
+            image,
+            lines,
+            source,
+            variables: prog.variables.iter().map(|v| (v.name.clone(), v.address)).collect(),
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            io: StdIo::default(),
+            step_limit,
+        }
+    }
+
+    fn line_at(&self, address: u16) -> Option<usize> {
+        self.image.lines.get(address as usize).copied().flatten()
+    }
+
+    fn variable(&self, name: &str) -> Result<u16, String> {
+        let name = name.strip_prefix('$').unwrap_or(name);
+        self.variables.get(name).copied().ok_or_else(|| format!("no variable `{}`", name))
+    }
+
+    fn address(&self, text: &str) -> Result<u16, String> {
+        if let Some(hex) = text.strip_prefix("0x") {
+            return u16::from_str_radix(hex, 16).map_err(|_| format!("invalid address `{}`", text));
+        }
+        if let Ok(address) = text.parse::<u16>() {
+            return Ok(address);
+        }
+        self.variable(text)
+    }
+
+    fn word(&self, address: u16) -> Result<i16, String> {
+        match self.machine.memory.get(address as usize) {
+            Some(&word) => Ok(word as i16),
+            None => Err(format!("address {} is outside memory", address)),
+        }
+    }
+
+    fn where_(&self) -> String {
+        let pc = self.machine.pc;
+        let instruction = self.lines.get(pc as usize).map(|l| l.kind.to_string()).unwrap_or_else(|| "?".to_string());
+        match self.line_at(pc) {
+            Some(line) => {
+                let (position, text) = (self.source.position(line), self.source.text(line));
+                format!("line {}: {}\n    {:03X}  {}", position, text, pc, instruction)
+            }
+            None => format!("{:03X}  {}", pc, instruction),
+        }
+    }
+
+    // `N` is a line of the main file, `file:N` a line of an included one, matched by the end of its path.
+    fn line_target(&self, target: &str) -> Option<(String, usize)> {
+        match target.rsplit_once(':') {
+            Some((file, line)) => {
+                let line = line.parse().ok()?;
+                let path = self.source.files().find(|path| *path == file || path.ends_with(&format!("/{}", file)))?;
+                Some((path.to_string(), line))
+            }
+            None => target.parse().ok().map(|line| (self.source.path().to_string(), line)),
+        }
+    }
+
+    fn break_at(&mut self, target: &str) -> Result<String, String> {
+        let target = target.strip_prefix('#').unwrap_or(target);
+        let located = |a: usize| self.image.lines[a].map(|line| self.source.locate(line));
+        let addresses: Vec<u16> = match self.line_target(target) {
+            // Every place the line's code starts, a line can be split up by the optimiser.
+            Some((file, line)) => {
+                let wanted = Some((file.as_str(), line));
+                (0..self.image.lines.len())
+                    .filter(|&a| located(a) == wanted && (a == 0 || located(a - 1) != wanted))
+                    .map(|a| a as u16)
+                    .collect()
+            }
+            // A subroutine's label names its return address word, its code starts after it.
+            None => self
+                .image
+                .symbols
+                .get(target)
+                .map(|&a| match self.lines.get(a as usize).map(|l| &l.kind) {
+                    Some(LineKind::Instruction(..)) => a,
+                    _ => a + 1,
+                })
+                .into_iter()
+                .collect(),
+        };
+        if addresses.is_empty() {
+            return Err(format!("no code for `{}`", target));
+        }
+
+        for &address in addresses.iter() {
+            self.breakpoints.push((address, target.to_string()));
+        }
+        Ok(format!("breakpoint on {} at {}", target, addresses.iter().map(|a| format!("{:03X}", a)).collect::<Vec<_>>().join(", ")))
+    }
+
+    // Runs one instruction and reports a watch that changed.
+    fn step(&mut self) -> Option<Stop> {
+        if self.machine.halted {
+            return Some(Stop::Halted);
+        }
+        if let Err(fault) = self.machine.step(&mut self.io) {
+            return Some(Stop::Fault(fault));
+        }
+        for watch in self.watches.iter_mut() {
+            let value = self.machine.memory[watch.address as usize] as i16;
+            if value != watch.value {
+                let old = std::mem::replace(&mut watch.value, value);
+                return Some(Stop::Watch(watch.name.clone(), old, value));
+            }
+        }
+        if self.machine.halted {
+            return Some(Stop::Halted);
+        }
+        None
+    }
+
+    fn resume(&mut self) -> Stop {
+        for _ in 0..self.step_limit {
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+            if let Some(k) = self.breakpoints.iter().position(|(a, _)| *a == self.machine.pc) {
+                return Stop::Breakpoint(k);
+            }
+        }
+        Stop::Limit
+    }
+
+    // Runs until the source line changes, going through code that has no source line of its own.
+    fn step_statement(&mut self) -> Stop {
+        let start = self.line_at(self.machine.pc);
+        for _ in 0..self.step_limit {
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+            let line = self.line_at(self.machine.pc);
+            if line.is_some() && line != start {
+                return Stop::Done;
+            }
+        }
+        Stop::Limit
+    }
+
+    fn report(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Breakpoint(k) => format!("breakpoint {} ({})\n{}", k, self.breakpoints[k].1, self.where_()),
+            Stop::Watch(name, old, new) => format!("{} changed from {} to {}\n{}", name, old, new, self.where_()),
+            Stop::Halted => match self.machine.failed_assertion(&self.image) {
+                Some(line) => format!(
+                    "program halted, line {}: {}",
+                    self.source.position(line),
+                    crate::assertion_message(line, &self.source.lines)
+                ),
+                None => "program halted".to_string(),
+            },
+            Stop::Fault(fault) => format!("error: {}\n{}", fault, self.where_()),
+            Stop::Limit => format!("stopped after {} instructions\n{}", self.step_limit, self.where_()),
+            Stop::Done => self.where_(),
+        }
+    }
+
+    fn command(&mut self, command: &str, argument: Option<&str>) -> Result<String, String> {
+        let count = || argument.map_or(Ok(1), |n| n.parse::<usize>().map_err(|_| format!("invalid count `{}`", n)));
+        let required = || argument.ok_or_else(|| format!("`{}` needs an argument", command));
+
+        match command {
+            "help" | "h" => Ok(HELP.to_string()),
+            "break" | "b" => self.break_at(required()?),
+            "delete" => match argument {
+                None => {
+                    self.breakpoints.clear();
+                    Ok("deleted all breakpoints".to_string())
+                }
+                Some(n) => match n.parse::<usize>() {
+                    Ok(k) if k < self.breakpoints.len() => {
+                        self.breakpoints.remove(k);
+                        Ok(format!("deleted breakpoint {}", k))
+                    }
+                    _ => Err(format!("no breakpoint `{}`", n)),
+                },
+            },
+            "watch" | "w" => {
+                let name = required()?.trim_start_matches('$').to_string();
+                let address = self.address(&name)?;
+                let value = self.word(address)?;
+                self.watches.push(Watch { name: name.clone(), address, value });
+                Ok(format!("watching {} = {}", name, value))
+            }
+            "unwatch" => {
+                let name = required()?.trim_start_matches('$');
+                let before = self.watches.len();
+                self.watches.retain(|w| w.name != name);
+                if self.watches.len() == before {
+                    return Err(format!("not watching `{}`", name));
+                }
+                Ok(format!("stopped watching {}", name))
+            }
+            "info" => {
+                let mut out: Vec<String> =
+                    self.breakpoints.iter().enumerate().map(|(k, (a, t))| format!("breakpoint {}: {} at {:03X}", k, t, a)).collect();
+                out.extend(self.watches.iter().map(|w| format!("watch: {} = {}", w.name, w.value)));
+                Ok(if out.is_empty() { "no breakpoints or watches".to_string() } else { out.join("\n") })
+            }
+            "continue" | "c" | "run" => {
+                let stop = self.resume();
+                Ok(self.report(stop))
+            }
+            "step" | "s" | "stepi" | "si" => {
+                let mut stop = Stop::Done;
+                for _ in 0..count()? {
+                    stop = if command.ends_with('i') { self.step().unwrap_or(Stop::Done) } else { self.step_statement() };
+                    if !matches!(stop, Stop::Done) {
+                        break;
+                    }
+                }
+                Ok(self.report(stop))
+            }
+            "print" | "p" => {
+                let target = required()?;
+                let (address, shown) = match target.strip_prefix('@') {
+                    Some(pointer) => (self.word(self.variable(pointer)?)? as u16 & 0xFFF, target.to_string()),
+                    None => (self.address(target)?, target.to_string()),
+                };
+                let value = self.word(address)?;
+                Ok(format!("{} = {} (hex {:04X}, at {:03X})", shown, value, value as u16, address))
+            }
+            "regs" => {
+                let m = &self.machine;
+                Ok(format!("AC {:>6}  PC {:03X}  MAR {:03X}  MBR {:>6}  IR {:04X}", m.ac, m.pc, m.mar, m.mbr, m.ir))
+            }
+            "where" | "list" => Ok(self.where_()),
+            _ => Err(format!("unknown command `{}`, try `help`", command)),
+        }
+    }
+
+    pub fn repl(&mut self) {
+        println!("{}", self.where_());
+        let mut last = String::new();
+        loop {
+            print!("(mdb) ");
+            let _ = io::stdout().flush();
+
+            let mut input = String::new();
+            if io::stdin().lock().read_line(&mut input).unwrap_or(0) == 0 {
+                break;
+            }
+            let input = if input.trim().is_empty() { last.clone() } else { input.trim().to_string() };
+
+            let mut words = input.split_whitespace();
+            let Some(command) = words.next() else { continue };
+            if command == "quit" || command == "q" {
+                break;
+            }
+            match self.command(command, words.next()) {
+                Ok(output) => println!("{}", output),
+                Err(message) => println!("error: {}", message),
+            }
+            last = input;
+        }
+    }
+}
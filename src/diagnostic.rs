@@ -0,0 +1,98 @@
+// Warnings and errors reported back to the user, tied to the source line they are about when there is one.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+    Note,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: Option<usize>,
+    // Last line of a diagnostic about a stretch of lines in one file.
+    pub end: Option<usize>,
+    // Set for lines of an included file, whose line is then counted within that file.
+    pub file: Option<String>,
+    pub message: String,
+    // More places the diagnostic is about, printed after it.
+    pub notes: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    pub fn warning(message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            line: None,
+            end: None,
+            file: None,
+            message: message.into(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            line: None,
+            end: None,
+            file: None,
+            message: message.into(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn note(message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Note,
+            line: None,
+            end: None,
+            file: None,
+            message: message.into(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn at(mut self, line: usize) -> Diagnostic {
+        self.line = Some(line);
+        self
+    }
+
+    pub fn through(mut self, end: usize) -> Diagnostic {
+        self.end = Some(end);
+        self
+    }
+
+    pub fn with_note(mut self, note: Diagnostic) -> Diagnostic {
+        self.notes.push(note);
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+            Severity::Note => "note",
+        };
+
+        let lines = match (self.line, self.end) {
+            (Some(line), Some(end)) if end > line => Some(format!("lines {}-{}", line, end)),
+            (Some(line), _) => Some(format!("line {}", line)),
+            (None, _) => None,
+        };
+        match (&self.file, lines) {
+            (Some(file), Some(lines)) => write!(f, "{}: {}, {}: {}", severity, file, lines, self.message)?,
+            (_, Some(lines)) => write!(f, "{}: {}: {}", severity, lines, self.message)?,
+            (_, None) => write!(f, "{}: {}", severity, self.message)?,
+        }
+        for note in self.notes.iter() {
+            write!(f, "\n{}", note)?;
+        }
+        Ok(())
+    }
+}
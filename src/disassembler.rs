@@ -0,0 +1,161 @@
+// Disassembler from machine words back to MARIE assembly. Code is found by following control flow from
+// address 0, so anything only reachable through jumpi is shown as data. Jump and jns targets and the
+// words instructions read or write get made-up labels unless the symbol table names them.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::diagnostic::Diagnostic;
+use crate::native::{Line, LineKind, NativeInstruction, Operand};
+
+// The symbol table at the end of a `--listing` file, or a file of just its `name address` lines, the
+// address in hex and optionally followed by the kind of symbol.
+pub fn read_symbols(source: &[String]) -> Result<HashMap<u16, String>, Vec<Diagnostic>> {
+    let start = source.iter().position(|line| line.trim() == "symbol table").map_or(0, |i| i + 1);
+    let mut symbols = HashMap::new();
+    let mut errors = Vec::new();
+    for (i, text) in source.iter().enumerate().skip(start) {
+        let tokens: Vec<&str> = text.split('/').next().unwrap_or_default().split_whitespace().collect();
+        match tokens[..] {
+            [] | ["name", "addr", "kind"] => {}
+            [name, address] | [name, address, "label" | "variable" | "constant"] => match u16::from_str_radix(address, 16) {
+                Ok(address) => {
+                    symbols.insert(address, name.to_string());
+                }
+                Err(_) => errors.push(Diagnostic::error(format!("`{}` is not a hex address", address)).at(i + 1)),
+            },
+            _ => errors.push(Diagnostic::error("expected `name address`").at(i + 1)),
+        }
+    }
+    if errors.is_empty() { Ok(symbols) } else { Err(errors) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Use {
+    Code,
+    // Read or written by an instruction.
+    Data,
+}
+
+fn decode(word: u16) -> Option<(NativeInstruction, u16)> {
+    NativeInstruction::from_opcode(word >> 12).map(|i| (i, word & 0xFFF))
+}
+
+pub fn disassemble(words: &[u16], symbols: &HashMap<u16, String>) -> Vec<Line> {
+    use NativeInstruction::*;
+    let mut uses: Vec<Option<Use>> = vec![None; words.len()];
+    // Made-up labels by address, the prefix tells what kind of word it is.
+    let mut labels: BTreeMap<u16, char> = BTreeMap::new();
+
+    let mut work = vec![0];
+    while let Some(address) = work.pop() {
+        let Some(&word) = words.get(address) else { continue };
+        if uses[address].is_some() {
+            continue;
+        }
+        let Some((instruction, operand)) = decode(word) else { continue };
+        uses[address] = Some(Use::Code);
+
+        match instruction {
+            Jump => {
+                labels.insert(operand, 'L');
+                work.push(operand as usize);
+            }
+            // The subroutine's first word holds the return address, its code follows.
+            Jns => {
+                labels.insert(operand, 'S');
+                if let Some(u) = uses.get_mut(operand as usize) {
+                    *u = Some(Use::Data);
+                }
+                work.extend([address + 1, operand as usize + 1]);
+            }
+            Skipcond => work.extend([address + 1, address + 2]),
+            Halt => {}
+            Jumpi => {
+                labels.entry(operand).or_insert('D');
+            }
+            Load | Store | Add | Subt | Addi | Loadi | Storei => {
+                labels.entry(operand).or_insert('D');
+                work.push(address + 1);
+            }
+            Input | Output | Clear => work.push(address + 1),
+        }
+    }
+
+    // Words something reads or writes are data even when control flow never reaches them.
+    for &address in labels.keys() {
+        if let Some(u @ None) = uses.get_mut(address as usize) {
+            *u = Some(Use::Data);
+        }
+    }
+
+    // Trailing zero words nothing uses are just the rest of memory.
+    let end = (0..words.len()).rev().find(|&a| words[a] != 0 || uses[a].is_some() || symbols.contains_key(&(a as u16)));
+    let end = end.map_or(0, |a| a + 1);
+
+    // Addresses past the end stay numbers, there is no line to put their label on.
+    let name = |address: u16| -> Option<String> {
+        if address as usize >= end {
+            return None;
+        }
+        symbols
+            .get(&address)
+            .cloned()
+            .or_else(|| labels.get(&address).map(|prefix| format!("{}{:03X}", prefix, address)))
+    };
+
+    (0..end)
+        .map(|address| {
+            let word = words[address];
+            let kind = match (uses[address], decode(word)) {
+                (Some(Use::Code), Some((instruction, _))) if !instruction.has_operand() => {
+                    LineKind::Instruction(instruction, None)
+                }
+                (Some(Use::Code), Some((Skipcond, condition))) => LineKind::Instruction(Skipcond, Some(Operand::Number(condition))),
+                (Some(Use::Code), Some((instruction, operand))) => LineKind::Instruction(
+                    instruction,
+                    Some(name(operand).map_or(Operand::Number(operand), Operand::Symbol)),
+                ),
+                (Some(Use::Data), _) => LineKind::Dec(word as i16),
+                _ => LineKind::Hex(word),
+            };
+            Line { label: name(address as u16), kind, source: None }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    fn assemble(source: &str) -> Vec<u16> {
+        let source: Vec<String> = source.lines().map(String::from).collect();
+        assembler::assemble(&source).unwrap_or_else(|errors| panic!("{:?}", errors)).words
+    }
+
+    // Disassembling and assembling again gives back the same words.
+    fn round_trip(source: &str) {
+        let words = assemble(source);
+        let text: Vec<String> = disassemble(&words, &HashMap::new()).iter().map(|l| l.to_string()).collect();
+        let again = assemble(&text.join("\n"));
+        let end = words.len().max(again.len());
+        let padded = |w: &[u16]| (0..end).map(|a| w.get(a).copied().unwrap_or(0)).collect::<Vec<_>>();
+        assert_eq!(padded(&again), padded(&words), "{}", text.join("\n"));
+    }
+
+    #[test]
+    fn code_and_data_round_trip() {
+        round_trip("load x\nskipcond 800\njump done\nadd x\nstore x\ndone, output\nhalt\nx, dec -3\nhex BEEF");
+    }
+
+    // Addresses past the end of the program stay numbers, and those above 9FF start with a letter.
+    #[test]
+    fn numeric_operands_round_trip() {
+        round_trip("load 0A00\nstore 0FFF\njns 0C10\nloadi 0ABC\nhalt");
+    }
+
+    #[test]
+    fn subroutines_round_trip() {
+        round_trip("jns sub\nhalt\nsub, hex 0\nload 0B00\noutput\njumpi sub");
+    }
+}
@@ -0,0 +1,332 @@
+// Machine-code file formats for an assembled image: plain hex words, Logisim's "v2.0 raw" ROM image,
+// Intel HEX and Verilog `$readmemh`. Each can be read back, which `disasm` uses to take any of them.
+
+use std::fmt::Write;
+
+use crate::diagnostic::Diagnostic;
+use crate::native::{Image, MEMORY_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Hex,
+    Logisim,
+    IntelHex,
+    Readmemh,
+}
+
+impl Format {
+    pub fn from_name(s: &str) -> Option<Format> {
+        match s {
+            "hex" => Some(Format::Hex),
+            "logisim" => Some(Format::Logisim),
+            "ihex" => Some(Format::IntelHex),
+            "readmemh" => Some(Format::Readmemh),
+            _ => None
+        }
+    }
+
+    // Works out the format of a file from its first meaningful line.
+    fn detect(source: &[String]) -> Format {
+        let first = source.iter().map(|l| l.trim()).find(|l| !l.is_empty()).unwrap_or_default();
+        if first.starts_with("v2.0 raw") {
+            Format::Logisim
+        }
+        else if first.starts_with(':') {
+            Format::IntelHex
+        }
+        else if first.starts_with('@') || first.starts_with("//") {
+            Format::Readmemh
+        }
+        else {
+            Format::Hex
+        }
+    }
+}
+
+pub fn write(format: Format, image: &Image) -> String {
+    let words = &image.words;
+    let mut out = String::new();
+    match format {
+        Format::Hex => {
+            for word in words.iter() {
+                let _ = writeln!(out, "{:04X}", word);
+            }
+        }
+
+        // Runs of four or more equal words are written as `count*word`, as Logisim does itself.
+        Format::Logisim => {
+            out.push_str("v2.0 raw\n");
+            let mut items = Vec::new();
+            let mut i = 0;
+            while i < words.len() {
+                let run = words[i..].iter().take_while(|&&w| w == words[i]).count();
+                if run >= 4 {
+                    items.push(format!("{}*{:x}", run, words[i]));
+                    i += run;
+                }
+                else {
+                    items.push(format!("{:x}", words[i]));
+                    i += 1;
+                }
+            }
+            for chunk in items.chunks(8) {
+                let _ = writeln!(out, "{}", chunk.join(" "));
+            }
+        }
+
+        // Addresses count words rather than bytes, which is what a 16-bit wide ROM expects. Each
+        // word is two bytes, high byte first.
+        Format::IntelHex => {
+            for (k, chunk) in words.chunks(8).enumerate() {
+                let address = (k * 8) as u16;
+                let data: Vec<u8> = chunk.iter().flat_map(|w| w.to_be_bytes()).collect();
+                let _ = writeln!(out, "{}", record(address, 0x00, &data));
+            }
+            let _ = writeln!(out, "{}", record(0, 0x01, &[]));
+        }
+
+        Format::Readmemh => {
+            out.push_str("// MARIE memory image, one 16-bit word per line\n@000\n");
+            for word in words.iter() {
+                let _ = writeln!(out, "{:04x}", word);
+            }
+        }
+    }
+    out
+}
+
+fn record(address: u16, kind: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(address.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+    bytes.push(checksum);
+
+    let mut text = String::from(":");
+    for byte in bytes {
+        let _ = write!(text, "{:02X}", byte);
+    }
+    text
+}
+
+// Reads words in any of the formats, telling them apart by their first line.
+pub fn read(source: &[String]) -> Result<Vec<u16>, Vec<Diagnostic>> {
+    let mut memory = Memory::default();
+    match Format::detect(source) {
+        Format::Logisim => read_logisim(source, &mut memory),
+        Format::IntelHex => read_intel_hex(source, &mut memory),
+        Format::Readmemh => read_readmemh(source, &mut memory),
+        Format::Hex => read_hex(source, &mut memory),
+    }
+    memory.finish()
+}
+
+#[derive(Default)]
+struct Memory {
+    words: Vec<u16>,
+    errors: Vec<Diagnostic>,
+}
+
+impl Memory {
+    fn put(&mut self, address: usize, word: u16, line: usize) {
+        if address >= MEMORY_SIZE {
+            self.errors.push(Diagnostic::error(format!("address {:X} is outside memory", address)).at(line));
+            return;
+        }
+        if self.words.len() <= address {
+            self.words.resize(address + 1, 0);
+        }
+        self.words[address] = word;
+    }
+
+    fn word(&mut self, token: &str, line: usize) -> Option<u16> {
+        let word = u16::from_str_radix(&token.replace('_', ""), 16).ok();
+        if word.is_none() {
+            self.errors.push(Diagnostic::error(format!("`{}` is not a 16-bit hex word", token)).at(line));
+        }
+        word
+    }
+
+    fn finish(self) -> Result<Vec<u16>, Vec<Diagnostic>> {
+        if self.errors.is_empty() { Ok(self.words) } else { Err(self.errors) }
+    }
+}
+
+// One or more hex words per line; anything after `/` is a comment.
+fn read_hex(source: &[String], memory: &mut Memory) {
+    let mut address = 0;
+    for (i, text) in source.iter().enumerate() {
+        for token in text.split('/').next().unwrap_or_default().split_whitespace() {
+            if let Some(word) = memory.word(token, i + 1) {
+                memory.put(address, word, i + 1);
+            }
+            address += 1;
+        }
+    }
+}
+
+fn read_logisim(source: &[String], memory: &mut Memory) {
+    let mut address = 0;
+    for (i, text) in source.iter().enumerate().skip(1) {
+        for token in text.split('#').next().unwrap_or_default().split_whitespace() {
+            let (count, token) = match token.split_once('*') {
+                Some((count, word)) => match count.parse::<usize>() {
+                    Ok(count) => (count, word),
+                    Err(_) => {
+                        memory.errors.push(Diagnostic::error(format!("invalid run length `{}`", count)).at(i + 1));
+                        continue;
+                    }
+                },
+                None => (1, token),
+            };
+            if address + count > MEMORY_SIZE {
+                memory.errors.push(
+                    Diagnostic::error(format!("run of {} words at {:X} goes past the end of memory", count, address)).at(i + 1),
+                );
+                return;
+            }
+            if let Some(word) = memory.word(token, i + 1) {
+                for _ in 0..count {
+                    memory.put(address, word, i + 1);
+                    address += 1;
+                }
+            }
+        }
+    }
+}
+
+fn read_intel_hex(source: &[String], memory: &mut Memory) {
+    for (i, text) in source.iter().enumerate() {
+        let line = i + 1;
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let bytes: Option<Vec<u8>> = text
+            .strip_prefix(':')
+            .filter(|hex| hex.len().is_multiple_of(2) && hex.len() >= 10)
+            .and_then(|hex| (0..hex.len()).step_by(2).map(|k| u8::from_str_radix(&hex[k..k + 2], 16).ok()).collect());
+        let Some(bytes) = bytes else {
+            memory.errors.push(Diagnostic::error("malformed Intel HEX record").at(line));
+            continue;
+        };
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            memory.errors.push(Diagnostic::error("Intel HEX checksum mismatch").at(line));
+            continue;
+        }
+        let length = bytes[0] as usize;
+        if bytes.len() != length + 5 {
+            memory.errors.push(Diagnostic::error("Intel HEX record length mismatch").at(line));
+            continue;
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        match bytes[3] {
+            0x00 if length.is_multiple_of(2) => {
+                for (k, pair) in bytes[4..4 + length].chunks(2).enumerate() {
+                    memory.put(address + k, u16::from_be_bytes([pair[0], pair[1]]), line);
+                }
+            }
+            0x00 => memory.errors.push(Diagnostic::error("Intel HEX data record with half a word").at(line)),
+            0x01 => break,
+            kind => memory.errors.push(Diagnostic::error(format!("unsupported Intel HEX record type {:02X}", kind)).at(line)),
+        }
+    }
+}
+
+fn read_readmemh(source: &[String], memory: &mut Memory) {
+    let mut address = 0;
+    for (i, text) in source.iter().enumerate() {
+        for token in text.split("//").next().unwrap_or_default().split_whitespace() {
+            match token.strip_prefix('@') {
+                Some(target) => match usize::from_str_radix(target, 16) {
+                    Ok(target) => address = target,
+                    Err(_) => memory.errors.push(Diagnostic::error(format!("invalid address `{}`", token)).at(i + 1)),
+                },
+                None => {
+                    if let Some(word) = memory.word(token, i + 1) {
+                        memory.put(address, word, i + 1);
+                    }
+                    address += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(words: Vec<u16>) -> Image {
+        let lines = vec![None; words.len()];
+        Image { words, symbols: Default::default(), lines }
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    fn round_trip(format: Format, words: Vec<u16>) {
+        let text = write(format, &image(words.clone()));
+        let source = lines(&text);
+        assert_eq!(Format::detect(&source), format);
+        assert_eq!(read(&source).unwrap(), words, "{:?}:\n{}", format, text);
+    }
+
+    // A jump over some data, a run long enough for Logisim's `count*word`, more than one Intel HEX
+    // record and words with every nibble used.
+    fn program() -> Vec<u16> {
+        let mut words = vec![0x9006, 0x0048, 0xFFFF, 0x8000, 0x1001, 0x6000, 0x7000];
+        words.extend([0xABCD; 5]);
+        words.extend([0x0123, 0x4567, 0x89EF, 0, 0, 0, 0]);
+        words
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        round_trip(Format::Hex, program());
+    }
+
+    #[test]
+    fn logisim_round_trip() {
+        round_trip(Format::Logisim, program());
+    }
+
+    #[test]
+    fn intel_hex_round_trip() {
+        round_trip(Format::IntelHex, program());
+    }
+
+    #[test]
+    fn readmemh_round_trip() {
+        round_trip(Format::Readmemh, program());
+    }
+
+    const FORMATS: [Format; 4] = [Format::Hex, Format::Logisim, Format::IntelHex, Format::Readmemh];
+
+    // What one format reads back, written out in another, reads back the same.
+    #[test]
+    fn formats_convert_into_each_other() {
+        for from in FORMATS {
+            let words = read(&lines(&write(from, &image(program())))).unwrap();
+            for to in FORMATS {
+                let text = write(to, &image(words.clone()));
+                assert_eq!(read(&lines(&text)).unwrap(), program(), "{:?} to {:?}:\n{}", from, to, text);
+            }
+        }
+    }
+
+    #[test]
+    fn logisim_runs_stop_at_the_end_of_memory() {
+        let full = format!("v2.0 raw\n1 {}*0\n", MEMORY_SIZE - 1);
+        assert_eq!(read(&lines(&full)).unwrap().len(), MEMORY_SIZE);
+
+        let past = format!("v2.0 raw\n1 {}*0\n", MEMORY_SIZE);
+        let errors = read(&lines(&past)).unwrap_err();
+        assert!(errors[0].message.contains("past the end of memory"), "{}", errors[0]);
+        assert!(read(&lines("v2.0 raw\n99999999999*7\n")).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+// The built-in instructions. Their operands have been checked against the signatures they are
+// registered with, so the handlers only pick them apart.
+
+use crate::ast;
+use crate::ir;
+use crate::registry::{OperandKind, Registry};
+use crate::Label;
+
+const CHECKED: &str = "operands are checked against the signatures";
+
+fn store_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    let [dest] = operands else { unreachable!("{}", CHECKED) };
+    let dest = ir.operand(dest)?;
+    ir.emit(ir::Instr::Copy { dest, src: ir::Operand::Acc });
+    Ok(())
+}
+
+fn load_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    let [src] = operands else { unreachable!("{}", CHECKED) };
+    let src = ir.operand(src)?;
+    ir.emit(ir::Instr::Copy { dest: ir::Operand::Acc, src });
+    Ok(())
+}
+
+fn clear_handler(_operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    ir.emit(ir::Instr::Copy { dest: ir::Operand::Acc, src: ir::Operand::Const(0) });
+    Ok(())
+}
+
+fn input_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    let dest = match operands {
+        [dest] => ir.operand(dest)?,
+        _ => ir::Operand::Acc,
+    };
+    ir.emit(ir::Instr::Input(dest));
+    Ok(())
+}
+
+fn output_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    let src = match operands {
+        [src] => ir.operand(src)?,
+        _ => ir::Operand::Acc,
+    };
+    ir.emit(ir::Instr::Output(src));
+    Ok(())
+}
+
+fn halt_handler(_operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    ir.emit(ir::Instr::Halt);
+    Ok(())
+}
+
+// `op $x` works on the accumulator, `op $d, $s` is `d = s op d` and `op $d, $a, $b` is `d = a op b`.
+// The two operand form loads the source and works the destination into it, so `subt $d, $s` is `d = s - d`.
+fn arithmetic(op: ir::BinOp, operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    let instr = match operands {
+        [src] => ir::Instr::Binary { dest: ir::Operand::Acc, op, lhs: ir::Operand::Acc, rhs: ir.operand(src)? },
+        [dest, src] => {
+            let dest = ir.operand(dest)?;
+            ir::Instr::Binary { dest: dest.clone(), op, lhs: ir.operand(src)?, rhs: dest }
+        }
+        [dest, src, src2] => ir::Instr::Binary { dest: ir.operand(dest)?, op, lhs: ir.operand(src)?, rhs: ir.operand(src2)? },
+        _ => unreachable!("{}", CHECKED),
+    };
+    ir.emit(instr);
+    Ok(())
+}
+
+fn add_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    arithmetic(ir::BinOp::Add, operands, ir)
+}
+
+fn subt_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    arithmetic(ir::BinOp::Sub, operands, ir)
+}
+
+fn label_operand(operands: &[ast::Operand]) -> Label {
+    match operands {
+        [ast::Operand::Label(label)] => label.clone(),
+        _ => unreachable!("{}", CHECKED),
+    }
+}
+
+// `jump #l, $a < $b` jumps when the comparison holds, leaving `a - b` in the accumulator.
+fn jump_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    let [ast::Operand::Label(target), lhs, ast::Operand::Relation(relation), rhs] = operands else {
+        ir.emit(ir::Instr::Jump(label_operand(operands)));
+        return Ok(());
+    };
+    let cond = ir::Cond::from_relation(relation).ok_or(format!("unknown comparison `{}`", relation))?;
+    let (lhs, rhs) = (ir.operand(lhs)?, ir.operand(rhs)?);
+    ir.emit(ir::Instr::Binary { dest: ir::Operand::Acc, op: ir::BinOp::Sub, lhs, rhs });
+    ir.emit(ir::Instr::Branch { value: ir::Operand::Acc, cond, target: target.clone() });
+    Ok(())
+}
+
+fn sub_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    let label = label_operand(operands);
+    ir.subroutine = Some(label.clone());
+    ir.emit(ir::Instr::Subroutine(label));
+    Ok(())
+}
+
+fn call_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    ir.emit(ir::Instr::Call(label_operand(operands)));
+    Ok(())
+}
+
+// A bare `return` leaves the subroutine declared last.
+fn return_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    let label = match operands {
+        [] => ir.subroutine.clone().ok_or("`return` outside of a subroutine")?,
+        _ => label_operand(operands),
+    };
+    ir.emit(ir::Instr::Return(label));
+    Ok(())
+}
+
+// `assert $x > $y` stops the program when the comparison is false, leaving the assert's line in
+// `_assert_line`. The accumulator is kept. Release builds drop asserts.
+fn assert_handler(operands: &[ast::Operand], ir: &mut ir::Builder) -> Result<(), String> {
+    let [lhs, ast::Operand::Relation(relation), rhs] = operands else { unreachable!("{}", CHECKED) };
+    let cond = ir::Cond::from_relation(relation).ok_or(format!("unknown comparison `{}`", relation))?;
+    // Before the operands, working one out may already emit code.
+    if ir.release {
+        return Ok(());
+    }
+    let (lhs, rhs) = (ir.operand(lhs)?, ir.operand(rhs)?);
+
+    let line = ir.line() as i16;
+    let save = ir.temp();
+    let ok = ir.fresh_label();
+    let failure = ir.assert_failure();
+    ir.emit(ir::Instr::Copy { dest: save.clone(), src: ir::Operand::Acc });
+    ir.emit(ir::Instr::Binary { dest: ir::Operand::Acc, op: ir::BinOp::Sub, lhs, rhs });
+    ir.emit(ir::Instr::Branch { value: ir::Operand::Acc, cond, target: ok.clone() });
+    ir.emit(ir::Instr::Copy { dest: ir::Operand::Acc, src: ir::Operand::Const(line) });
+    ir.emit(ir::Instr::Jump(failure));
+    ir.emit(ir::Instr::Label(ok));
+    ir.emit(ir::Instr::Copy { dest: ir::Operand::Acc, src: save });
+    Ok(())
+}
+
+pub fn register(registry: &mut Registry) {
+    use OperandKind::{Label, Relation, Value};
+
+    let none = || vec![vec![]];
+    let value = || vec![vec![Value]];
+    let optional_value = || vec![vec![], vec![Value]];
+    let label = || vec![vec![Label]];
+    let arithmetic = || vec![vec![Value], vec![Value, Value], vec![Value, Value, Value]];
+
+    registry.register_fn("store", value(), store_handler);
+    registry.register_fn("load", value(), load_handler);
+    registry.register_fn("clear", none(), clear_handler);
+    registry.register_fn("input", optional_value(), input_handler);
+    registry.register_fn("output", optional_value(), output_handler);
+    registry.register_fn("halt", none(), halt_handler);
+    registry.register_fn("add", arithmetic(), add_handler);
+    registry.register_fn("subt", arithmetic(), subt_handler);
+    registry.register_fn("jump", vec![vec![Label], vec![Label, Value, Relation, Value]], jump_handler);
+    registry.register_fn("sub", label(), sub_handler);
+    registry.register_fn("call", label(), call_handler);
+    registry.register_fn("return", vec![vec![], vec![Label]], return_handler);
+    registry.register_fn("assert", vec![vec![Value, Relation, Value]], assert_handler);
+}
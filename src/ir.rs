@@ -0,0 +1,651 @@
+// Three-address intermediate representation. Handlers turn statements into explicit operations on
+// variables, the accumulator and virtual temporaries (`t1 = $a + $b`), which are checked by `verify`
+// and only then lowered to accumulator code.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::ast;
+use crate::diagnostic::Diagnostic;
+use crate::native::{self, NativeInstruction};
+use crate::{Label, Program, Reference, ReferenceType, SimpleInstruction, SkipcondType};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Acc,
+    Var(String),
+    Const(i16),
+    Temp(usize),
+    AddressOf(String),
+    Deref(Box<Operand>),         // the word a variable, temporary or constant points at
+    Index(String, Box<Operand>), // the word `offset` past the one a variable points at
+    Element(String, Box<Operand>), // the address `offset` words past a variable or label
+    Cell(String, Box<Operand>, Box<Operand>), // a word of a two-dimensional array by row and column
+}
+
+impl Operand {
+    fn is_writable(&self) -> bool {
+        !matches!(self, Operand::Const(_) | Operand::AddressOf(_) | Operand::Element(..))
+    }
+
+    fn temps(&self) -> Vec<usize> {
+        match self {
+            Operand::Temp(k) => vec![*k],
+            Operand::Deref(inner) | Operand::Index(_, inner) | Operand::Element(_, inner) => inner.temps(),
+            Operand::Cell(_, row, column) => [row.temps(), column.temps()].concat(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Acc => write!(f, "acc"),
+            Operand::Var(name) => write!(f, "${}", name),
+            Operand::Const(value) => write!(f, "{}", value),
+            Operand::Temp(k) => write!(f, "t{}", k),
+            Operand::AddressOf(name) => write!(f, "&{}", name),
+            Operand::Deref(inner) => match &**inner {
+                Operand::Var(name) => write!(f, "@{}", name),
+                inner => write!(f, "@{}", inner),
+            },
+            Operand::Index(name, offset) => write!(f, "@{}[{}]", name, offset),
+            Operand::Element(name, offset) => write!(f, "&{}[{}]", name, offset),
+            Operand::Cell(name, row, column) => write!(f, "${}[{}][{}]", name, row, column),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+}
+
+// How a branch compares its value against zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    Gt,
+    Lt,
+    Eq,
+    Le,
+    Ge,
+    Ne,
+}
+
+impl Cond {
+    pub fn negate(self) -> Cond {
+        match self {
+            Cond::Gt => Cond::Le,
+            Cond::Lt => Cond::Ge,
+            Cond::Eq => Cond::Ne,
+            Cond::Le => Cond::Gt,
+            Cond::Ge => Cond::Lt,
+            Cond::Ne => Cond::Eq,
+        }
+    }
+
+    pub(crate) fn from_skipcond(cond: &SkipcondType) -> Cond {
+        match cond {
+            SkipcondType::GreaterThanZero => Cond::Gt,
+            SkipcondType::LessThanZero => Cond::Lt,
+            SkipcondType::Zero => Cond::Eq,
+        }
+    }
+
+    pub fn from_relation(relation: &str) -> Option<Cond> {
+        [Cond::Gt, Cond::Lt, Cond::Eq, Cond::Le, Cond::Ge, Cond::Ne].into_iter().find(|c| c.symbol() == relation)
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Cond::Gt => ">",
+            Cond::Lt => "<",
+            Cond::Eq => "==",
+            Cond::Le => "<=",
+            Cond::Ge => ">=",
+            Cond::Ne => "!=",
+        }
+    }
+}
+
+// What an asm line refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsmOperand {
+    Var(String),
+    Const(i16),
+    Label(Label),
+    Address(u16),
+}
+
+impl fmt::Display for AsmOperand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmOperand::Var(name) => write!(f, "${}", name),
+            AsmOperand::Const(value) => write!(f, "{}", value),
+            AsmOperand::Label(label) => write!(f, "{}", label),
+            AsmOperand::Address(address) => write!(f, "{:03X}", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Copy { dest: Operand, src: Operand },
+    Binary { dest: Operand, op: BinOp, lhs: Operand, rhs: Operand },
+    Input(Operand),
+    Output(Operand),
+    Label(Label),
+    Jump(Label),
+    Branch { value: Operand, cond: Cond, target: Label },
+    Subroutine(Label),
+    Call(Label),
+    Return(Label),
+    Halt,
+    // A MARIE instruction written by hand, which goes into the program as it is.
+    Asm { instruction: NativeInstruction, operand: Option<AsmOperand> },
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instr::Copy { dest, src } => write!(f, "{} = {}", dest, src),
+            Instr::Binary { dest, op, lhs, rhs } => {
+                let op = match op {
+                    BinOp::Add => "+",
+                    BinOp::Sub => "-",
+                };
+                write!(f, "{} = {} {} {}", dest, lhs, op, rhs)
+            }
+            Instr::Input(dest) => write!(f, "{} = input", dest),
+            Instr::Output(src) => write!(f, "output {}", src),
+            Instr::Label(label) => write!(f, "{}:", label),
+            Instr::Jump(label) => write!(f, "goto {}", label),
+            Instr::Branch { value, cond, target } => write!(f, "if {} {} 0 goto {}", value, cond.symbol(), target),
+            Instr::Subroutine(label) => write!(f, "sub {}:", label),
+            Instr::Call(label) => write!(f, "call {}", label),
+            Instr::Return(label) => write!(f, "return {}", label),
+            Instr::Halt => write!(f, "halt"),
+            Instr::Asm { instruction, operand: Some(operand) } => write!(f, "asm {} {}", instruction, operand),
+            Instr::Asm { instruction, operand: None } => write!(f, "asm {}", instruction),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Code {
+    pub instrs: Vec<Instr>,
+    pub lines: Vec<usize>, // source line of each instruction, 0 for code the compiler adds
+    pub temps: usize,
+    pub words: Vec<String>, // data words the code needs besides the declared variables and temporaries
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for instr in self.instrs.iter() {
+            match instr {
+                Instr::Label(_) | Instr::Subroutine(_) => writeln!(f, "{}", instr)?,
+                _ => writeln!(f, "    {}", instr)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// What handlers write their IR into.
+#[derive(Debug, Default)]
+pub struct Builder {
+    code: Code,
+    line: usize,
+    labels: usize,
+    pub subroutine: Option<Label>,
+    // Asserts compile to nothing.
+    pub release: bool,
+    asserts: bool,
+}
+
+impl Builder {
+    pub fn at(&mut self, line: usize) {
+        self.line = line;
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn emit(&mut self, instr: Instr) {
+        self.code.instrs.push(instr);
+        self.code.lines.push(self.line);
+    }
+
+    pub fn temp(&mut self) -> Operand {
+        self.code.temps += 1;
+        Operand::Temp(self.code.temps)
+    }
+
+    // Source labels start with a letter, so these can't collide with them.
+    pub fn fresh_label(&mut self) -> Label {
+        self.labels += 1;
+        format!("_L{}", self.labels)
+    }
+
+    pub fn operand(&mut self, operand: &ast::Operand) -> Result<Operand, String> {
+        match operand {
+            ast::Operand::Number(value) => Ok(Operand::Const(*value)),
+            ast::Operand::Var(name) => Ok(Operand::Var(name.clone())),
+            ast::Operand::Pointer(name) => Ok(Operand::Deref(Box::new(Operand::Var(name.clone())))),
+            ast::Operand::Address(name) => Ok(Operand::AddressOf(name.clone())),
+            ast::Operand::Index(name, offset) => Ok(Operand::Index(name.clone(), Box::new(self.operand(offset)?))),
+            ast::Operand::Element(name, offset) => Ok(Operand::Element(name.clone(), Box::new(self.operand(offset)?))),
+            ast::Operand::Indirect(name, depth) => {
+                Ok((0..*depth).fold(Operand::Var(name.clone()), |inner, _| Operand::Deref(Box::new(inner))))
+            }
+            ast::Operand::Cell(name, row, column) => {
+                Ok(Operand::Cell(name.clone(), Box::new(self.operand(row)?), Box::new(self.operand(column)?)))
+            }
+            ast::Operand::Deref(address) => match self.operand(address)? {
+                // `@(&x)` is x itself.
+                Operand::AddressOf(name) => Ok(Operand::Var(name)),
+                address @ (Operand::Var(_) | Operand::Temp(_) | Operand::Const(_) | Operand::Deref(_)) => Ok(Operand::Deref(Box::new(address))),
+                address => Ok(Operand::Deref(Box::new(self.computed(address)))),
+            },
+            ast::Operand::Label(_) | ast::Operand::Code(_) | ast::Operand::Relation(_) => {
+                Err(format!("expected a value, found `{}`", operand))
+            }
+        }
+    }
+
+    // Works an address out into a temporary ahead of the instruction going through it. The accumulator
+    // is kept, that instruction may still need it.
+    fn computed(&mut self, address: Operand) -> Operand {
+        let (save, temp) = (self.temp(), self.temp());
+        self.emit(Instr::Copy { dest: save.clone(), src: Operand::Acc });
+        self.emit(Instr::Copy { dest: temp.clone(), src: address });
+        self.emit(Instr::Copy { dest: Operand::Acc, src: save });
+        temp
+    }
+
+    // The routine failed asserts jump to with their source line in the accumulator. It is added once, at
+    // the end of the code, when some assert needs it.
+    pub fn assert_failure(&mut self) -> Label {
+        self.asserts = true;
+        ASSERT_FAIL.to_string()
+    }
+
+    pub fn finish(mut self) -> Code {
+        if self.asserts {
+            self.at(0);
+            self.emit(Instr::Label(ASSERT_FAIL.to_string()));
+            self.emit(Instr::Copy { dest: Operand::Var(ASSERT_LINE.to_string()), src: Operand::Acc });
+            self.emit(Instr::Halt);
+            self.code.words.push(ASSERT_LINE.to_string());
+        }
+        self.code
+    }
+}
+
+// Checks the invariants lowering relies on.
+pub fn verify(code: &Code) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+    let mut error = |message: String, line: usize| errors.push(Diagnostic::error(message).at(line));
+
+    let mut labels = HashSet::new();
+    let mut subroutines = HashSet::new();
+    for (instr, &line) in code.instrs.iter().zip(code.lines.iter()) {
+        if let Instr::Label(label) | Instr::Subroutine(label) = instr {
+            if !labels.insert(label.as_str()) {
+                error(format!("label `{}` is defined more than once", label), line);
+            }
+            if let Instr::Subroutine(_) = instr {
+                subroutines.insert(label.as_str());
+            }
+        }
+    }
+
+    let mut defined = HashSet::new();
+    for (instr, &line) in code.instrs.iter().zip(code.lines.iter()) {
+        let (reads, writes): (Vec<&Operand>, Vec<&Operand>) = match instr {
+            Instr::Copy { dest, src } => (vec![src], vec![dest]),
+            Instr::Binary { dest, lhs, rhs, .. } => {
+                if *rhs == Operand::Acc {
+                    error(format!("`{}` reads the accumulator after loading the left operand", instr), line);
+                }
+                (vec![lhs, rhs], vec![dest])
+            }
+            Instr::Input(dest) => (Vec::new(), vec![dest]),
+            Instr::Output(src) => (vec![src], Vec::new()),
+            Instr::Branch { value, target, .. } => {
+                if !labels.contains(target.as_str()) {
+                    error(format!("branch to undefined label `{}`", target), line);
+                }
+                (vec![value], Vec::new())
+            }
+            Instr::Jump(target) => {
+                if !labels.contains(target.as_str()) {
+                    error(format!("jump to undefined label `{}`", target), line);
+                }
+                (Vec::new(), Vec::new())
+            }
+            Instr::Call(target) | Instr::Return(target) => {
+                if !subroutines.contains(target.as_str()) {
+                    error(format!("`{}` is not a subroutine", target), line);
+                }
+                (Vec::new(), Vec::new())
+            }
+            Instr::Asm { operand: Some(AsmOperand::Label(target)), .. } => {
+                if !labels.contains(target.as_str()) {
+                    error(format!("asm refers to undefined label `{}`", target), line);
+                }
+                (Vec::new(), Vec::new())
+            }
+            Instr::Label(_) | Instr::Subroutine(_) | Instr::Halt | Instr::Asm { .. } => (Vec::new(), Vec::new()),
+        };
+
+        for operand in reads.iter().chain(writes.iter()) {
+            match operand {
+                Operand::Deref(inner) if !matches!(pointer(inner), Operand::Var(_) | Operand::Temp(_) | Operand::Const(_)) => {
+                    error(format!("`{}` can only go through a variable, a temporary or a constant address", operand), line);
+                }
+                Operand::Index(_, offset) | Operand::Element(_, offset) if !matches!(**offset, Operand::Var(_) | Operand::Const(_)) => {
+                    error(format!("`{}` needs a variable or constant offset", operand), line);
+                }
+                Operand::Cell(_, row, column)
+                    if [row, column].iter().any(|i| !matches!(***i, Operand::Var(_) | Operand::Const(_))) =>
+                {
+                    error(format!("`{}` needs variable or constant indices", operand), line);
+                }
+                _ => {}
+            }
+        }
+
+        // Temporaries are written once before they are read, so a linear walk is enough.
+        for operand in reads.iter() {
+            for k in operand.temps() {
+                if !defined.contains(&k) {
+                    error(format!("t{} is read before it is written", k), line);
+                }
+            }
+        }
+        for operand in writes.iter() {
+            if !operand.is_writable() {
+                error(format!("can't assign to `{}`", operand), line);
+            }
+            if let Operand::Temp(k) = operand {
+                defined.insert(*k);
+            }
+            else {
+                for k in operand.temps() {
+                    if !defined.contains(&k) {
+                        error(format!("t{} is read before it is written", k), line);
+                    }
+                }
+            }
+        }
+    }
+
+    errors
+}
+
+// What a chain of dereferences starts from.
+fn pointer(operand: &Operand) -> &Operand {
+    match operand {
+        Operand::Deref(inner) => pointer(inner),
+        _ => operand,
+    }
+}
+
+// Scratch words the expansions of indexed and indirect-subtract references work in.
+const SCRATCH: [&str; 3] = ["temp_acc", "temp_addr", "temp_val"];
+
+struct Lowering<'p> {
+    prog: &'p mut Program,
+    labels: HashSet<Label>,
+    skips: usize,
+    line: usize,
+    errors: Vec<Diagnostic>,
+}
+
+impl Lowering<'_> {
+    fn emit(&mut self, instruction: SimpleInstruction) {
+        self.prog.add_instruction(instruction, self.line);
+    }
+
+    fn variable(&mut self, name: &str) -> Option<crate::Variable> {
+        let var = self.prog.get_variable(name).cloned();
+        if var.is_none() {
+            self.errors.push(Diagnostic::error(format!("undeclared variable `{}`", name)).at(self.line));
+        }
+        var
+    }
+
+    fn scratch(&mut self) {
+        for name in SCRATCH {
+            if self.prog.get_variable(name).is_none() {
+                self.prog.add_variable(name, 0, false);
+            }
+        }
+    }
+
+    fn reference(&mut self, operand: &Operand) -> Option<Reference> {
+        match operand {
+            Operand::Var(name) => self.variable(name).map(|v| Reference::new(v, ReferenceType::Direct)),
+            Operand::Temp(k) => self.variable(&temp_name(*k)).map(|v| Reference::new(v, ReferenceType::Direct)),
+            Operand::Const(value) => Some(Reference::new(self.prog.constant(*value), ReferenceType::Direct)),
+            Operand::AddressOf(name) => self.address_word(name).map(|v| Reference::new(v, ReferenceType::Direct)),
+            Operand::Element(name, offset) => {
+                let address = self.address_word(name)?;
+                let offset = self.reference(offset)?;
+                Some(Reference::new(address, ReferenceType::Element(offset.var)))
+            }
+            Operand::Deref(inner) => {
+                let r = self.reference(inner)?;
+                let reference_type = match r.reference_type {
+                    ReferenceType::Direct => ReferenceType::Pointer,
+                    ReferenceType::Pointer => ReferenceType::Indirect(2),
+                    ReferenceType::Indirect(depth) => ReferenceType::Indirect(depth + 1),
+                    _ => unreachable!("verify only lets pointers go through variables, temporaries and constants"),
+                };
+                if let ReferenceType::Indirect(_) = reference_type {
+                    self.scratch();
+                }
+                Some(Reference::new(r.var, reference_type))
+            }
+            Operand::Cell(name, row, column) => {
+                let row_length = match self.variable(name)?.row_length {
+                    Some(row_length) => row_length,
+                    None => {
+                        self.errors.push(Diagnostic::error(format!("`${}` is not a two-dimensional array", name)).at(self.line));
+                        return None;
+                    }
+                };
+                self.scratch();
+                let address = self.address_word(name)?;
+                let (row, column) = (self.reference(row)?.var, self.reference(column)?.var);
+                Some(Reference::new(address, ReferenceType::Grid { row, column, row_length }))
+            }
+            Operand::Index(name, offset) => {
+                self.scratch();
+                let base = self.variable(name)?;
+                let offset = self.reference(offset)?;
+                Some(Reference::new(base, ReferenceType::Offset(offset.var)))
+            }
+            Operand::Acc => unreachable!("the accumulator is not a memory reference"),
+        }
+    }
+
+    // The constant `x_addr` holding the address of a variable or label.
+    fn address_word(&mut self, name: &str) -> Option<crate::Variable> {
+        let address = match self.prog.get_variable(name) {
+            Some(var) => var.address,
+            None if self.labels.contains(name) => 0, // filled in by layout
+            None => {
+                self.errors.push(Diagnostic::error(format!("`&{}` names neither a variable nor a label", name)).at(self.line));
+                return None;
+            }
+        };
+        let addr_name = format!("{}_addr", name);
+        if self.prog.get_variable(&addr_name).is_none() {
+            self.prog.add_variable(&addr_name, address as i16, true);
+        }
+        self.variable(&addr_name)
+    }
+
+    fn load(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Acc => {}
+            Operand::Const(0) => self.emit(SimpleInstruction::Clear),
+            _ => {
+                if let Some(r) = self.reference(operand) {
+                    self.emit(SimpleInstruction::Load(r));
+                }
+            }
+        }
+    }
+
+    fn store(&mut self, operand: &Operand) {
+        if *operand != Operand::Acc {
+            if let Some(r) = self.reference(operand) {
+                self.emit(SimpleInstruction::Store(r));
+            }
+        }
+    }
+
+    fn skip_label(&mut self) -> Label {
+        self.skips += 1;
+        format!("_skip{}", self.skips)
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        use SimpleInstruction as SI;
+        match instr {
+            Instr::Copy { dest, src } => {
+                self.load(src);
+                self.store(dest);
+            }
+            Instr::Binary { dest, op, lhs, rhs } => {
+                self.load(lhs);
+                if let Operand::Deref(_) | Operand::Index(..) = rhs {
+                    if *op == BinOp::Sub {
+                        self.scratch();
+                    }
+                }
+                if let Some(r) = self.reference(rhs) {
+                    self.emit(match op {
+                        BinOp::Add => SI::Add(r),
+                        BinOp::Sub => SI::Subt(r),
+                    });
+                }
+                self.store(dest);
+            }
+            Instr::Input(dest) => {
+                self.emit(SI::Input);
+                self.store(dest);
+            }
+            Instr::Output(src) => {
+                self.load(src);
+                self.emit(SI::Output);
+            }
+            Instr::Label(label) => self.emit(SI::Label(label.clone())),
+            Instr::Jump(label) => self.emit(SI::Jump(label.clone())),
+            Instr::Branch { value, cond, target } => {
+                self.load(value);
+                // Skipcond skips the instruction after it, so the conditions it tests directly jump when they fail.
+                let direct = match cond {
+                    Cond::Le => Some(SkipcondType::GreaterThanZero),
+                    Cond::Ge => Some(SkipcondType::LessThanZero),
+                    Cond::Ne => Some(SkipcondType::Zero),
+                    _ => None,
+                };
+                match direct {
+                    Some(skip) => {
+                        self.emit(SI::Skipcond(skip));
+                        self.emit(SI::Jump(target.clone()));
+                    }
+                    None => {
+                        let skip = match cond {
+                            Cond::Gt => SkipcondType::GreaterThanZero,
+                            Cond::Lt => SkipcondType::LessThanZero,
+                            _ => SkipcondType::Zero,
+                        };
+                        let over = self.skip_label();
+                        self.emit(SI::Skipcond(skip));
+                        self.emit(SI::Jump(over.clone()));
+                        self.emit(SI::Jump(target.clone()));
+                        self.emit(SI::Label(over));
+                    }
+                }
+            }
+            // Jns stores the return address in the word the subroutine's label names, and enters after it.
+            Instr::Subroutine(label) => {
+                self.emit(SI::Label(label.clone()));
+                self.emit(SI::Word(0));
+            }
+            Instr::Call(label) => self.emit(SI::Jns(label.clone())),
+            Instr::Return(label) => self.emit(SI::JumpI(label.clone())),
+            Instr::Halt => self.emit(SI::Halt),
+            Instr::Asm { instruction, operand } => {
+                let operand = match operand {
+                    Some(AsmOperand::Var(name)) => match self.variable(name) {
+                        Some(var) => Some(native::Operand::Symbol(var.name)),
+                        None => return,
+                    },
+                    Some(AsmOperand::Const(value)) => Some(native::Operand::Symbol(self.prog.constant(*value).name)),
+                    Some(AsmOperand::Label(label)) => Some(native::Operand::Symbol(label.clone())),
+                    Some(AsmOperand::Address(address)) => Some(native::Operand::Number(*address)),
+                    None => None,
+                };
+                self.emit(SI::Asm(native::LineKind::Instruction(*instruction, operand)));
+            }
+        }
+    }
+}
+
+pub const ASSERT_FAIL: &str = "_assert_fail";
+// Holds the source line of the assert that failed, zero while none has.
+pub const ASSERT_LINE: &str = "_assert_line";
+
+pub fn temp_name(k: usize) -> String {
+    format!("_t{}", k)
+}
+
+// Appends the accumulator code for `code` to the program, declaring its temporaries as data words.
+pub(crate) fn lower(code: &Code, prog: &mut Program) -> Result<(), Vec<Diagnostic>> {
+    for k in 1..=code.temps {
+        prog.add_variable(&temp_name(k), 0, false);
+    }
+    for name in code.words.iter() {
+        if prog.get_variable(name).is_none() {
+            prog.add_variable(name, 0, false);
+        }
+    }
+
+    let labels = code
+        .instrs
+        .iter()
+        .filter_map(|i| match i {
+            Instr::Label(label) | Instr::Subroutine(label) => Some(label.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut lowering = Lowering {
+        prog,
+        labels,
+        skips: 0,
+        line: 0,
+        errors: Vec::new(),
+    };
+
+    for (instr, &line) in code.instrs.iter().zip(code.lines.iter()) {
+        lowering.line = line;
+        lowering.instr(instr);
+    }
+
+    if lowering.errors.is_empty() {
+        Ok(())
+    }
+    else {
+        Err(lowering.errors)
+    }
+}
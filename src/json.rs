@@ -0,0 +1,21 @@
+// Helpers for the hand-written JSON the profiler and source map produce.
+
+pub fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// A JSON string literal.
+pub fn string(text: &str) -> String {
+    format!("\"{}\"", escape(text))
+}
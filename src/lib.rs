@@ -0,0 +1,663 @@
+
+mod api;
+pub mod assembler;
+pub mod ast;
+mod cfg;
+mod dce;
+pub mod debugger;
+pub mod diagnostic;
+pub mod disassembler;
+pub mod formats;
+mod handlers;
+pub mod ir;
+mod json;
+pub mod listing;
+mod macros;
+pub mod modules;
+pub mod native;
+pub mod passes;
+pub mod peephole;
+pub mod profile;
+mod redundant;
+pub mod registry;
+pub mod simulator;
+pub mod sourcemap;
+mod target;
+pub mod testing;
+mod unused;
+
+pub use api::{compile, CompileOptions, CompiledProgram};
+pub use passes::Level;
+pub use target::Target;
+
+use diagnostic::Diagnostic;
+use registry::Registry;
+
+use std::collections::HashMap;
+use std::fmt;
+use regex::Regex;
+
+
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub default_value: i16,
+    pub constant: bool,
+    pub address: u16,
+    pub line: Option<usize>, // where it was declared, words the compiler adds have none
+    // Words taken, more than one for an array, whose words after the first start at zero.
+    pub size: u16,
+    // Words per row of a two-dimensional array.
+    pub row_length: Option<u16>,
+}
+
+#[derive(Debug, Clone)]
+struct VariableGenerator {
+    address: u16,
+}
+
+impl VariableGenerator {
+    fn new(start_address: u16) -> VariableGenerator {
+        VariableGenerator {
+            address: start_address,
+        }
+    }
+
+    fn generate(&mut self, name: &str, default_value: i16, constant: bool) -> Variable {
+        let var = Variable {
+            name: name.to_string(),
+            default_value,
+            constant,
+            address: self.address,
+            line: None,
+            size: 1,
+            row_length: None,
+        };
+
+        self.address += 1;
+        var
+    }
+}
+
+impl Default for VariableGenerator {
+    fn default() -> Self {
+        VariableGenerator { address: 1 }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ReferenceType {
+    Direct,
+    Pointer,
+    Offset(Variable),
+    // The variable holds an address, the value is that address plus the offset.
+    Element(Variable),
+    // The variable is a pointer followed this many times, two or more.
+    Indirect(usize),
+    // The variable holds an array's address, the word is at row * row length + column past it.
+    Grid { row: Variable, column: Variable, row_length: u16 },
+}
+
+#[derive(Debug, Clone)]
+struct Reference {
+    var: Variable,
+    reference_type: ReferenceType,
+}
+
+impl Reference {
+    fn new(var: Variable, reference_type: ReferenceType) -> Reference {
+        Reference {
+            var,
+            reference_type,
+        }
+    }
+}
+
+impl Reference {
+    // Code leaving the address of an indexed or indirect word in the accumulator.
+    fn address(&self, op: &dyn Fn(native::NativeInstruction, &str) -> native::LineKind) -> Vec<native::LineKind> {
+        use native::NativeInstruction as NI;
+        let name = &self.var.name;
+        match &self.reference_type {
+            ReferenceType::Offset(off) => vec![op(NI::Load, name), op(NI::Add, &off.name)],
+            ReferenceType::Indirect(depth) => {
+                let mut code = vec![op(NI::Loadi, name)];
+                for _ in 2..*depth {
+                    code.extend([op(NI::Store, "temp_addr"), op(NI::Loadi, "temp_addr")]);
+                }
+                code
+            }
+            // row * row length by shift and add over the bits of the row length, from the top one down.
+            // Doubling goes through temp_addr, which only gets the address at the end.
+            ReferenceType::Grid { row, column, row_length } => {
+                let mut code = Vec::new();
+                let bits = 16 - row_length.leading_zeros();
+                for bit in (0..bits).rev() {
+                    if !code.is_empty() {
+                        code.extend([op(NI::Store, "temp_addr"), op(NI::Add, "temp_addr")]);
+                    }
+                    if row_length & (1 << bit) != 0 {
+                        code.push(op(if code.is_empty() { NI::Load } else { NI::Add }, &row.name));
+                    }
+                }
+                if code.is_empty() {
+                    code.push(native::LineKind::Instruction(NI::Clear, None));
+                }
+                code.extend([op(NI::Add, &column.name), op(NI::Add, name)]);
+                code
+            }
+            _ => unreachable!("`{}` is a word of its own", self),
+        }
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.reference_type {
+            ReferenceType::Direct => write!(f, "${}", self.var.name),
+            ReferenceType::Pointer => write!(f, "@{}", self.var.name),
+            ReferenceType::Offset(off) => write!(f, "${}[${}]", self.var.name, off.name),
+            ReferenceType::Element(off) => write!(f, "${} + ${}", self.var.name, off.name),
+            ReferenceType::Indirect(depth) => write!(f, "{}{}", "@".repeat(*depth), self.var.name),
+            ReferenceType::Grid { row, column, .. } => write!(f, "@{}[${}][${}]", self.var.name, row.name, column.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum SkipcondType {
+    GreaterThanZero,
+    LessThanZero,
+    Zero,
+}
+
+impl SkipcondType {
+    fn from_str(s: &str) -> Option<SkipcondType> {
+        match s {
+            "800" => Some(SkipcondType::GreaterThanZero),
+            "000" => Some(SkipcondType::LessThanZero),
+            "400" => Some(SkipcondType::Zero),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for SkipcondType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SkipcondType::GreaterThanZero => "800",
+            SkipcondType::LessThanZero => "000",
+            SkipcondType::Zero => "400",
+        })
+    }
+}
+
+type Label = String;
+
+#[derive(Debug, Clone)]
+enum SimpleInstruction {
+    Add(Reference), Subt(Reference), Store(Reference), Load(Reference),
+    Skipcond(SkipcondType),
+    Jump(Label), Jns(Label), JumpI(Label),
+    Label(Label),
+    Word(i16),
+    Clear, Input, Output, Halt,
+    // Hand-written in an asm block, left exactly as it is.
+    Asm(native::LineKind),
+}
+
+impl SimpleInstruction {
+    // Whether the instruction may skip the one after it.
+    fn skips(&self) -> bool {
+        matches!(
+            self,
+            SimpleInstruction::Skipcond(_)
+                | SimpleInstruction::Asm(native::LineKind::Instruction(native::NativeInstruction::Skipcond, _))
+        )
+    }
+
+    // The word or label an asm line refers to.
+    fn asm_symbol(&self) -> Option<&str> {
+        match self {
+            SimpleInstruction::Asm(native::LineKind::Instruction(_, Some(native::Operand::Symbol(name)))) => Some(name),
+            _ => None,
+        }
+    }
+
+    // The MARIE words the instruction expands to. A label takes no word of its own.
+    fn native(&self) -> Vec<native::LineKind> {
+        use SimpleInstruction as SI;
+        use native::NativeInstruction as NI;
+        let op = |instruction: NI, name: &str| {
+            native::LineKind::Instruction(instruction, Some(native::Operand::Symbol(name.to_string())))
+        };
+        let direct = match self {
+            SI::Add(_) => NI::Add,
+            SI::Subt(_) => NI::Subt,
+            SI::Store(_) => NI::Store,
+            _ => NI::Load,
+        };
+        let indirect = match self {
+            SI::Add(_) => NI::Addi,
+            SI::Store(_) => NI::Storei,
+            _ => NI::Loadi,
+        };
+
+        match self {
+            SI::Load(reference) | SI::Add(reference) | SI::Store(reference) | SI::Subt(reference) => {
+                let name = &reference.var.name;
+                match &reference.reference_type {
+                    ReferenceType::Direct => vec![op(direct, name)],
+                    // Only ever read: the address, then the offset added or subtracted with it.
+                    ReferenceType::Element(off) => {
+                        vec![op(direct, name), op(if matches!(self, SI::Subt(_)) { NI::Subt } else { NI::Add }, &off.name)]
+                    }
+                    ReferenceType::Pointer if !matches!(self, SI::Subt(_)) => vec![op(indirect, name)],
+                    // There is no subti, so the subtrahend is fetched into temp_val first.
+                    ReferenceType::Pointer => vec![
+                        op(NI::Store, "temp_acc"), op(NI::Loadi, name), op(NI::Store, "temp_val"), op(NI::Load, "temp_acc"),
+                        op(NI::Subt, "temp_val"),
+                    ],
+                    // The word's address is worked out in the accumulator and used through temp_addr.
+                    ReferenceType::Offset(_) | ReferenceType::Indirect(_) | ReferenceType::Grid { .. } => {
+                        let address = reference.address(&op);
+                        let through = |tail: Vec<native::LineKind>| [address.clone(), vec![op(NI::Store, "temp_addr")], tail].concat();
+                        match self {
+                            SI::Load(_) => through(vec![op(NI::Loadi, "temp_addr")]),
+                            SI::Subt(_) => [
+                                vec![op(NI::Store, "temp_acc")],
+                                through(vec![op(NI::Loadi, "temp_addr"), op(NI::Store, "temp_val"), op(NI::Load, "temp_acc"), op(NI::Subt, "temp_val")]),
+                            ].concat(),
+                            _ => [vec![op(NI::Store, "temp_acc")], through(vec![op(NI::Load, "temp_acc"), op(indirect, "temp_addr")])].concat(),
+                        }
+                    }
+                }
+            }
+
+            SI::Skipcond(cond) => vec![native::LineKind::Instruction(
+                NI::Skipcond,
+                Some(native::Operand::Number(u16::from_str_radix(&cond.to_string(), 16).unwrap())),
+            )],
+            SI::Jump(label) => vec![op(NI::Jump, label)],
+            SI::Jns(label) => vec![op(NI::Jns, label)],
+            SI::JumpI(label) => vec![op(NI::Jumpi, label)],
+            SI::Label(_) => Vec::new(),
+            SI::Word(value) => vec![native::LineKind::Hex(*value as u16)],
+            SI::Clear => vec![native::LineKind::Instruction(NI::Clear, None)],
+            SI::Input => vec![native::LineKind::Instruction(NI::Input, None)],
+            SI::Output => vec![native::LineKind::Instruction(NI::Output, None)],
+            SI::Halt => vec![native::LineKind::Instruction(NI::Halt, None)],
+            SI::Asm(kind) => vec![kind.clone()],
+        }
+    }
+
+    fn to_native(&self) -> String {
+        match self {
+            SimpleInstruction::Label(label) => format!("{},", label),
+            _ => self.native().iter().map(|l| l.to_string()).collect::<Vec<_>>().join("\n"),
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Program {
+    instructions: Vec<SimpleInstruction>,
+    lines: Vec<usize>, // source line of each instruction
+    variables: Vec<Variable>,
+    var_gen: VariableGenerator,
+}
+
+fn validate(reg_str: &str, str_to_validate: &str) -> bool {
+    let re = Regex::new(reg_str).unwrap();
+    if let Some(captured) = re.captures(str_to_validate) {
+        let first_capture = captured.get(0).unwrap(); // Can't panic cause if it were of size 0, it would be None.
+        first_capture.start() == 0 && first_capture.end() == str_to_validate.len()
+    }
+    else {
+        false
+    }
+}
+
+
+impl Program {
+    fn add_instruction(&mut self, instruction: SimpleInstruction, line: usize) {
+        self.instructions.push(instruction);
+        self.lines.push(line);
+    }
+
+    // Whether a skipcond may skip the instruction. Labels take no memory, so one between the two
+    // doesn't keep the skipcond from reaching it.
+    fn skipped(&self, i: usize) -> bool {
+        self.instructions[..i].iter().rev().find(|x| !matches!(x, SimpleInstruction::Label(_))).is_some_and(|x| x.skips())
+    }
+
+    // Replaces a range of instructions, the replacement is attributed to the line of the first one replaced.
+    fn splice(&mut self, range: std::ops::Range<usize>, replacement: Vec<SimpleInstruction>) {
+        let line = self.lines.get(range.start).copied().unwrap_or_default();
+        let count = replacement.len();
+        self.instructions.splice(range.clone(), replacement);
+        self.lines.splice(range, std::iter::repeat_n(line, count));
+    }
+
+    fn add_variable(&mut self, name: &str, default_value: i16, constant: bool) {
+        self.variables.push(
+            self.var_gen.generate(name, default_value, constant)
+        );
+    }
+
+    // The data words, in address order.
+    pub fn variables(&self) -> &[Variable] {
+        &self.variables
+    }
+
+    // Each instruction of the code as MARIE assembly, one or more lines of it, or `name,` for a label.
+    pub fn instructions(&self) -> Vec<String> {
+        self.instructions.iter().map(|i| i.to_native()).collect()
+    }
+
+    // Source line of each instruction, in the order they appear in the code. 0 for code with no line.
+    pub fn source_lines(&self) -> &[usize] {
+        &self.lines
+    }
+
+    pub fn get_variable(&self, name: &str) -> Option<&Variable> {
+        self.variables.iter().find(|v| v.name == name)
+    }
+
+    // The data word holding a literal, added the first time the literal is used.
+    fn constant(&mut self, value: i16) -> Variable {
+        let name = if value < 0 { format!("c_m{}", value.unsigned_abs()) } else { format!("c_{}", value) };
+        if self.get_variable(&name).is_none() {
+            self.add_variable(&name, value, true);
+        }
+        self.get_variable(&name).unwrap().clone()
+    }
+
+    // Number of memory words the data takes.
+    pub fn data_size(&self) -> u16 {
+        self.variables.iter().map(|v| v.size).sum()
+    }
+
+    // Number of memory words the code takes once expanded to MARIE.
+    pub fn code_size(&self) -> usize {
+        self.native().len() - 1 - self.data_size() as usize
+    }
+
+    // The control-flow graph of the code in Graphviz dot.
+    pub fn cfg_dot(&self) -> String {
+        cfg::Cfg::build(&self.instructions).to_dot(self)
+    }
+
+    // The whole memory image as MARIE lines, starting at address 0.
+    pub fn native(&self) -> Vec<native::Line> {
+        // Address 0 jumps over the data words, which the generator numbers from 1.
+        let mut lines = vec![native::Line::new(native::LineKind::Instruction(
+            native::NativeInstruction::Jump,
+            Some(native::Operand::Symbol("main".to_string())),
+        ))];
+        for var in self.variables.iter() {
+            lines.push(native::Line {
+                label: Some(var.name.clone()),
+                ..native::Line::new(native::LineKind::Dec(var.default_value))
+            });
+            for _ in 1..var.size {
+                lines.push(native::Line::new(native::LineKind::Dec(0)));
+            }
+        }
+
+        // MARIE wants a label on the same line as the word it names, so hold it until the next instruction.
+        let mut pending: Option<&Label> = None;
+        for (instruction, &line) in self.instructions.iter().zip(self.lines.iter()) {
+            let source = (line != 0).then_some(line);
+            if let SimpleInstruction::Label(label) = instruction {
+                if let Some(previous) = pending {
+                    lines.push(native::Line {
+                        label: Some(previous.clone()),
+                        kind: native::LineKind::Instruction(
+                            native::NativeInstruction::Jump,
+                            Some(native::Operand::Symbol(label.clone())),
+                        ),
+                        source,
+                    });
+                }
+                pending = Some(label);
+                continue;
+            }
+
+            for kind in instruction.native() {
+                lines.push(native::Line { label: pending.take().cloned(), kind, source });
+            }
+        }
+
+        if let Some(label) = pending {
+            lines.push(native::Line {
+                label: Some(label.clone()),
+                ..native::Line::new(native::LineKind::Instruction(native::NativeInstruction::Halt, None))
+            });
+        }
+        lines
+    }
+
+    // Renumbers the data words from 1 after some were dropped, and points every `x_addr` constant at
+    // `x` again, whether `x` is a variable or a label in the code that follows the data.
+    fn layout(&mut self) {
+        let mut address = 1;
+        for var in self.variables.iter_mut() {
+            var.address = address;
+            address += var.size;
+        }
+
+        // Taken from the lines themselves, which include the jumps joining labels that follow each other.
+        let addresses: HashMap<String, u16> = self.native()
+            .into_iter()
+            .enumerate()
+            .filter_map(|(k, line)| line.label.map(|label| (label, k as u16)))
+            .collect();
+
+        for var in self.variables.iter_mut() {
+            if let Some(address) = var.name.strip_suffix("_addr").and_then(|n| addresses.get(n)) {
+                var.default_value = *address as i16;
+            }
+        }
+
+        self.var_gen = VariableGenerator::new(self.data_size() + 1);
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.native() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+use std::fs::File;
+use std::path::Path;
+use std::io::{self, BufRead};
+
+// opens up a file with name in the argument, and returns list of lines, empty ones included so that
+// line i of the file sits at index i - 1
+pub fn read_file(file_name: &str) -> Result<Vec<String>, io::Error> {
+    let path = Path::new(file_name);
+    let file = File::open(path)?;
+    let reader = io::BufReader::new(file);
+    let mut lines = Vec::new();
+    for line in reader.lines() {
+        lines.push(line?);
+    }
+    Ok(lines)
+}
+
+// Todo add array initialization
+// Todo add multiple variable declaration in one line
+fn statement(stmt: &ast::Statement, registry: &Registry, ir: &mut ir::Builder) -> Result<(), String> {
+    ir.at(stmt.line);
+    match &stmt.kind {
+        ast::StatementKind::Var { name, value: Some(value) } => {
+            ir.emit(ir::Instr::Copy { dest: ir::Operand::Var(name.clone()), src: ir::Operand::Const(*value) });
+            Ok(())
+        }
+        ast::StatementKind::Var { .. } | ast::StatementKind::Const { .. } | ast::StatementKind::Array { .. } => Ok(()),
+        // Resolved when the source is loaded.
+        ast::StatementKind::Include(_) | ast::StatementKind::Module(_) => Ok(()),
+        ast::StatementKind::Macro { .. } | ast::StatementKind::EndMacro => Ok(()),
+        ast::StatementKind::Asm { label, instruction, operand } => {
+            if let Some(label) = label {
+                ir.emit(ir::Instr::Label(label.clone()));
+            }
+            let operand = match operand {
+                Some(ast::Operand::Var(name)) => Some(ir::AsmOperand::Var(name.clone())),
+                Some(ast::Operand::Number(value)) => Some(ir::AsmOperand::Const(*value)),
+                Some(ast::Operand::Label(label)) => Some(ir::AsmOperand::Label(label.clone())),
+                Some(ast::Operand::Code(code)) => Some(ir::AsmOperand::Address(u16::from_str_radix(code, 16).map_err(|e| e.to_string())?)),
+                Some(other) => return Err(format!("`{}` can't be used in an asm block", other)),
+                None => None,
+            };
+            ir.emit(ir::Instr::Asm { instruction: *instruction, operand });
+            Ok(())
+        }
+        ast::StatementKind::Label(label) => {
+            ir.emit(ir::Instr::Label(label.clone()));
+            Ok(())
+        }
+        ast::StatementKind::Assign { dest, lhs, rhs } => {
+            let (dest, lhs) = (ir.operand(dest)?, ir.operand(lhs)?);
+            let instr = match rhs {
+                None => ir::Instr::Copy { dest, src: lhs },
+                Some((op, rhs)) => {
+                    let op = if *op == '+' { ir::BinOp::Add } else { ir::BinOp::Sub };
+                    ir::Instr::Binary { dest, op, lhs, rhs: ir.operand(rhs)? }
+                }
+            };
+            ir.emit(instr);
+            Ok(())
+        }
+        ast::StatementKind::Instruction { mnemonic, .. } if mnemonic == "skipcond" => {
+            Err("`skipcond` needs a statement after it to skip".to_string())
+        }
+        ast::StatementKind::Instruction { mnemonic, operands } => registry.build(mnemonic, operands, ir),
+    }
+}
+
+// `skipcond` skips the statement after it, so the two become one branch. Skipping a jump is the usual
+// case and turns into a branch to the jump's target when the condition fails.
+fn skipcond(operands: &[ast::Operand], next: &ast::Statement, registry: &Registry, ir: &mut ir::Builder) -> Result<(), String> {
+    let cond = match operands {
+        [ast::Operand::Code(code)] => SkipcondType::from_str(code).ok_or(format!("unknown skipcond condition `{}`", code))?,
+        _ => return Err("`skipcond` takes one of 000, 400 or 800".to_string()),
+    };
+    let cond = ir::Cond::from_skipcond(&cond);
+
+    if let ast::StatementKind::Instruction { mnemonic, operands } = &next.kind {
+        if let ("jump", [ast::Operand::Label(target)]) = (mnemonic.as_str(), operands.as_slice()) {
+            ir.emit(ir::Instr::Branch { value: ir::Operand::Acc, cond: cond.negate(), target: target.clone() });
+            return Ok(());
+        }
+    }
+
+    let over = ir.fresh_label();
+    ir.emit(ir::Instr::Branch { value: ir::Operand::Acc, cond, target: over.clone() });
+    statement(next, registry, ir)?;
+    ir.emit(ir::Instr::Label(over));
+    Ok(())
+}
+
+// Turns the statements into IR with the instructions in the registry.
+pub fn build_ir(statements: &[ast::Statement], registry: &Registry, release: bool) -> Result<ir::Code, Vec<Diagnostic>> {
+    let mut ir = ir::Builder::default();
+    ir.release = release;
+    let mut errors = Vec::new();
+
+    let mut i = 0;
+    while i < statements.len() {
+        let stmt = &statements[i];
+        let result = match &stmt.kind {
+            ast::StatementKind::Instruction { mnemonic, operands } if mnemonic == "skipcond" => {
+                ir.at(stmt.line);
+                i += 1;
+                match statements.get(i) {
+                    Some(next) => skipcond(operands, next, registry, &mut ir),
+                    None => Err("`skipcond` needs a statement after it to skip".to_string()),
+                }
+            }
+            _ => statement(stmt, registry, &mut ir),
+        };
+
+        if let Err(message) = result {
+            errors.push(Diagnostic::error(message).at(stmt.line));
+        }
+        i += 1;
+    }
+
+    if errors.is_empty() {
+        Ok(ir.finish())
+    }
+    else {
+        Err(errors)
+    }
+}
+
+fn declare_variables(statements: &[ast::Statement], prog: &mut Program) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+    for stmt in statements.iter() {
+        let (name, value, constant, dims) = match &stmt.kind {
+            ast::StatementKind::Var { name, value } => (name, value.unwrap_or(0), false, None),
+            ast::StatementKind::Const { name, value } => (name, *value, true, None),
+            ast::StatementKind::Array { name, dims } => (name, 0, false, Some(dims)),
+            _ => continue,
+        };
+
+        if prog.get_variable(name).is_some() {
+            errors.push(Diagnostic::error(format!("`{}` is declared more than once", name)).at(stmt.line));
+        }
+        else {
+            let Some(size) = dims.map_or(Some(1), |dims| ast::array_size(dims)) else {
+                errors.push(Diagnostic::error(format!("array `{}` is too large", name)).at(stmt.line));
+                continue;
+            };
+            let mut var = prog.var_gen.generate(name, value, constant);
+            var.line = Some(stmt.line);
+            var.size = size;
+            var.row_length = dims.and_then(|dims| dims.get(1).copied());
+            prog.var_gen.address += size - 1;
+            prog.variables.push(var);
+        }
+    }
+    errors
+}
+
+// AST -> IR, with the program's declared variables.
+pub fn front_end(source: &modules::Source, registry: &Registry, release: bool) -> Result<(Program, ir::Code), Vec<Diagnostic>> {
+    let place = |errors: Vec<Diagnostic>| errors.into_iter().map(|e| source.place(e)).collect::<Vec<_>>();
+
+    let mut prog = Program::default();
+    let errors = declare_variables(&source.statements, &mut prog);
+    if !errors.is_empty() {
+        return Err(place(errors));
+    }
+
+    let code = build_ir(&source.statements, registry, release).map_err(place)?;
+    let errors = ir::verify(&code);
+    if !errors.is_empty() {
+        return Err(place(errors));
+    }
+    Ok((prog, code))
+}
+
+// IR -> MARIE, optimised, fitted to the target and laid out. Returns the pass statistics and warnings,
+// whose lines are still to be placed in their files with `Source::place`.
+pub fn back_end(code: &ir::Code, prog: &mut Program, passes: &passes::PassManager, target: Target) -> Result<(passes::Stats, Vec<Diagnostic>), Vec<Diagnostic>> {
+    ir::lower(code, prog)?;
+    let result = passes.run(prog);
+    target::lower(prog, target)?;
+    prog.layout();
+    Ok(result)
+}
+
+// The message for an assert that failed on the line.
+pub fn assertion_message(line: usize, source: &[String]) -> String {
+    let text = line.checked_sub(1).and_then(|i| source.get(i)).map(|s| s.trim()).unwrap_or_default();
+    format!("assertion failed: `{}`", text)
+}
@@ -0,0 +1,49 @@
+// Annotated listing: every word of the image with its address, encoding, MARIE text and the source line
+// that produced it, followed by the symbol table. A source line is printed once, next to the first word
+// it expands into, numbered within its own file.
+
+use std::fmt::Write;
+
+use crate::modules::Source;
+use crate::native::{Image, Line};
+use crate::Program;
+
+pub fn listing(prog: &Program, lines: &[Line], image: &Image, source: &Source) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "addr  word  {:<28} {:>5}  source", "assembly", "line");
+
+    let mut previous = None;
+    for (address, line) in lines.iter().enumerate() {
+        // Data words point back at their declaration.
+        let number = line.source.or_else(|| {
+            line.label.as_ref().and_then(|label| prog.get_variable(label)).and_then(|v| v.line)
+        });
+
+        let text = line.to_string().replace('\t', " ");
+        let (number_text, source_text) = match number {
+            Some(n) if previous != Some(n) => (source.position(n), source.text(n)),
+            _ => (String::new(), ""),
+        };
+        previous = number;
+
+        let row = format!(
+            "{:03X}   {:04X}  {:<28} {:>5}  {}",
+            address, image.words[address], text, number_text, source_text
+        );
+        let _ = writeln!(out, "{}", row.trim_end());
+    }
+
+    let mut symbols: Vec<(&String, &u16)> = image.symbols.iter().collect();
+    symbols.sort_by_key(|&(name, address)| (*address, name.clone()));
+
+    let _ = writeln!(out, "\nsymbol table\n{:<20} addr  kind", "name");
+    for (name, address) in symbols {
+        let kind = match prog.get_variable(name) {
+            Some(var) if var.constant => "constant",
+            Some(_) => "variable",
+            None => "label",
+        };
+        let _ = writeln!(out, "{:<20} {:03X}   {}", name, address, kind);
+    }
+    out
+}
@@ -0,0 +1,314 @@
+// Macros. `macro name($a, #b) { ... }` defines a statement that is replaced by its body wherever it is
+// used, with the operands of the use put in place of the parameters. Names the body declares are its
+// own: each expansion renames them after the macro and the line it is used on, so two expansions never
+// share a label or a variable. Other names refer to whatever they name where the macro is used.
+//
+// Every expanded statement gets a line of its own, a copy of the body line it came from, and an
+// `Expansion` saying where the body line and the use are, so diagnostics can point at both.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Operand, Statement, StatementKind};
+use crate::diagnostic::Diagnostic;
+
+pub struct Expansion {
+    pub name: String,
+    pub body: usize,
+    pub call: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Statement>,
+    // Names the body declares.
+    locals: HashSet<String>,
+}
+
+// Takes the macro definitions out of a file's statements and expands their uses. The expanded lines are
+// added to `lines`, and what they came from to `expansions`.
+pub fn expand(
+    statements: Vec<Statement>,
+    lines: &mut Vec<String>,
+    expansions: &mut HashMap<usize, Expansion>,
+) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+    let mut expander = Expander { macros: HashMap::new(), lines, expansions, errors: Vec::new() };
+    let statements = expander.collect(statements);
+
+    let mut result = Vec::new();
+    let mut skipcond = false;
+    for stmt in statements {
+        expander.statement(stmt, skipcond, &mut Vec::new(), &mut result);
+        skipcond = matches!(&result.last(), Some(Statement { kind: StatementKind::Instruction { mnemonic, .. }, .. }) if mnemonic == "skipcond");
+    }
+
+    if expander.errors.is_empty() {
+        Ok(result)
+    }
+    else {
+        Err(expander.errors)
+    }
+}
+
+struct Expander<'a> {
+    macros: HashMap<String, Macro>,
+    lines: &'a mut Vec<String>,
+    expansions: &'a mut HashMap<usize, Expansion>,
+    errors: Vec<Diagnostic>,
+}
+
+impl Expander<'_> {
+    // Pulls out the definitions, returning the statements left.
+    fn collect(&mut self, statements: Vec<Statement>) -> Vec<Statement> {
+        let mut rest = Vec::new();
+        let mut current: Option<(String, usize, Macro)> = None;
+        for stmt in statements {
+            match (&stmt.kind, &mut current) {
+                (StatementKind::Macro { .. }, Some(_)) => {
+                    self.errors.push(Diagnostic::error("macros can't be defined inside a macro").at(stmt.line));
+                }
+                (StatementKind::Macro { name, params }, None) => {
+                    let definition = Macro { params: params.clone(), body: Vec::new(), locals: HashSet::new() };
+                    current = Some((name.clone(), stmt.line, definition));
+                }
+                (StatementKind::EndMacro, Some(_)) => {
+                    let (name, line, mut definition) = current.take().unwrap();
+                    definition.locals = definition.body.iter().filter_map(|s| s.declares()).map(String::from).collect();
+                    if self.macros.insert(name.clone(), definition).is_some() {
+                        self.errors.push(Diagnostic::error(format!("macro `{}` is defined more than once", name)).at(line));
+                    }
+                }
+                (StatementKind::EndMacro, None) => {
+                    self.errors.push(Diagnostic::error("`}` without a `macro`").at(stmt.line));
+                }
+                (StatementKind::Include(_) | StatementKind::Module(_), Some(_)) => {
+                    self.errors.push(Diagnostic::error("`include` and `module` can't be used inside a macro").at(stmt.line));
+                }
+                (_, Some((_, _, definition))) => definition.body.push(stmt),
+                (_, None) => rest.push(stmt),
+            }
+        }
+
+        if let Some((name, line, _)) = current {
+            self.errors.push(Diagnostic::error(format!("macro `{}` has no closing `}}`", name)).at(line));
+        }
+        rest
+    }
+
+    // Expands one statement into `out`. `active` holds the macros being expanded, to catch one that
+    // uses itself.
+    fn statement(&mut self, stmt: Statement, skipcond: bool, active: &mut Vec<String>, out: &mut Vec<Statement>) {
+        let StatementKind::Instruction { mnemonic, operands } = &stmt.kind else {
+            out.push(stmt);
+            return;
+        };
+        let Some(definition) = self.macros.get(mnemonic) else {
+            out.push(stmt);
+            return;
+        };
+
+        let name = mnemonic.clone();
+        if active.contains(&name) {
+            self.errors.push(Diagnostic::error(format!("macro `{}` uses itself", name)).at(stmt.line));
+            return;
+        }
+        if operands.len() != definition.params.len() {
+            let message = format!("macro `{}` takes {} operands, found {}", name, definition.params.len(), operands.len());
+            self.errors.push(Diagnostic::error(message).at(stmt.line));
+            return;
+        }
+
+        let args: HashMap<&str, &Operand> = definition.params.iter().map(String::as_str).zip(operands.iter()).collect();
+        let prefix = format!("_{}_{}_", name, stmt.line);
+        let substitution = Substitution { args, locals: &definition.locals, prefix: &prefix };
+        let mut body = Vec::new();
+        for body_stmt in definition.body.iter() {
+            match substitution.statement(body_stmt) {
+                Ok(kind) => body.push((body_stmt.line, kind)),
+                Err(message) => {
+                    let error = Diagnostic::error(message).at(body_stmt.line);
+                    self.errors.push(error.with_note(Diagnostic::note(format!("in `{}` used here", name)).at(stmt.line)));
+                }
+            }
+        }
+
+        // `skipcond` skips the statement after it, which has to be the whole expansion. The jump it
+        // skips goes around the body, the one it doesn't skip goes into it.
+        let label = |suffix: &str| format!("{}{}", prefix, suffix);
+        if skipcond {
+            out.push(Statement { kind: jump(&label("run")), ..stmt.clone() });
+            out.push(Statement { kind: jump(&label("done")), ..stmt.clone() });
+            out.push(Statement { kind: StatementKind::Label(label("run")), ..stmt.clone() });
+        }
+
+        active.push(name.clone());
+        let mut after_skipcond = false;
+        for (body_line, kind) in body {
+            self.lines.push(self.lines[body_line - 1].clone());
+            let line = self.lines.len();
+            self.expansions.insert(line, Expansion { name: name.clone(), body: body_line, call: stmt.line });
+
+            let expanded = Statement { line, kind, public: false };
+            self.statement(expanded, after_skipcond, active, out);
+            after_skipcond = matches!(&out.last(), Some(Statement { kind: StatementKind::Instruction { mnemonic, .. }, .. }) if mnemonic == "skipcond");
+        }
+        active.pop();
+
+        if skipcond {
+            out.push(Statement { kind: StatementKind::Label(label("done")), ..stmt });
+        }
+    }
+}
+
+fn jump(label: &str) -> StatementKind {
+    StatementKind::Instruction { mnemonic: "jump".to_string(), operands: vec![Operand::Label(label.to_string())] }
+}
+
+struct Substitution<'a> {
+    args: HashMap<&'a str, &'a Operand>,
+    locals: &'a HashSet<String>,
+    prefix: &'a str,
+}
+
+impl Substitution<'_> {
+    fn name(&self, name: &str) -> String {
+        if self.locals.contains(name) {
+            format!("{}{}", self.prefix, name)
+        }
+        else {
+            name.to_string()
+        }
+    }
+
+    // The variable a parameter used as `@p`, `&p`, `@p[...]`, `&p[...]`, `@@p` or `$p[...][...]` stands
+    // for.
+    fn variable(&self, name: &str, sigil: char) -> Result<String, String> {
+        match self.args.get(name) {
+            Some(Operand::Var(var)) => Ok(var.clone()),
+            Some(arg) => Err(format!("`{}{}` needs a variable, found `{}`", sigil, name, arg)),
+            None => Ok(self.name(name)),
+        }
+    }
+
+    fn operand(&self, operand: &Operand) -> Result<Operand, String> {
+        Ok(match operand {
+            Operand::Var(name) => match self.args.get(name.as_str()) {
+                Some(&arg) => arg.clone(),
+                None => Operand::Var(self.name(name)),
+            },
+            Operand::Pointer(name) => Operand::Pointer(self.variable(name, '@')?),
+            Operand::Address(name) => Operand::Address(self.variable(name, '&')?),
+            Operand::Index(name, offset) => Operand::Index(self.variable(name, '@')?, Box::new(self.operand(offset)?)),
+            Operand::Element(name, offset) => Operand::Element(self.variable(name, '&')?, Box::new(self.operand(offset)?)),
+            Operand::Indirect(name, depth) => Operand::Indirect(self.variable(name, '@')?, *depth),
+            Operand::Cell(name, row, column) => {
+                Operand::Cell(self.variable(name, '$')?, Box::new(self.operand(row)?), Box::new(self.operand(column)?))
+            }
+            Operand::Deref(address) => Operand::Deref(Box::new(self.operand(address)?)),
+            Operand::Label(name) => match self.args.get(name.as_str()) {
+                Some(Operand::Label(label)) => Operand::Label(label.clone()),
+                Some(arg) => return Err(format!("`#{}` needs a label, found `{}`", name, arg)),
+                None => Operand::Label(self.name(name)),
+            },
+            Operand::Number(_) | Operand::Code(_) | Operand::Relation(_) => operand.clone(),
+        })
+    }
+
+    fn declaration(&self, name: &str) -> Result<String, String> {
+        if self.args.contains_key(name) {
+            return Err(format!("`{}` is a parameter and can't be declared", name));
+        }
+        Ok(self.name(name))
+    }
+
+    fn statement(&self, stmt: &Statement) -> Result<StatementKind, String> {
+        Ok(match &stmt.kind {
+            StatementKind::Var { name, value } => StatementKind::Var { name: self.declaration(name)?, value: *value },
+            StatementKind::Const { name, value } => StatementKind::Const { name: self.declaration(name)?, value: *value },
+            StatementKind::Array { name, dims } => StatementKind::Array { name: self.declaration(name)?, dims: dims.clone() },
+            StatementKind::Label(name) => StatementKind::Label(self.declaration(name)?),
+            StatementKind::Instruction { mnemonic, operands } => StatementKind::Instruction {
+                mnemonic: mnemonic.clone(),
+                operands: operands.iter().map(|o| self.operand(o)).collect::<Result<_, _>>()?,
+            },
+            StatementKind::Assign { dest, lhs, rhs } => StatementKind::Assign {
+                dest: self.operand(dest)?,
+                lhs: self.operand(lhs)?,
+                rhs: rhs.as_ref().map(|(op, rhs)| Ok::<_, String>((*op, self.operand(rhs)?))).transpose()?,
+            },
+            StatementKind::Asm { label, instruction, operand } => StatementKind::Asm {
+                label: label.as_deref().map(|l| self.declaration(l)).transpose()?,
+                instruction: *instruction,
+                operand: operand.as_ref().map(|o| self.operand(o)).transpose()?,
+            },
+            kind => kind.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulator::{BufferIo, Machine};
+    use crate::{compile, modules, CompileOptions};
+
+    fn output(source: &str, input: &[i16]) -> Vec<i16> {
+        let compiled = compile(source, &CompileOptions::default()).unwrap_or_else(|errors| panic!("{:?}", errors));
+        let mut io = BufferIo { input: input.iter().copied().collect(), output: Vec::new() };
+        Machine::new(compiled.image()).run(&mut io, 10_000).unwrap();
+        io.output
+    }
+
+    fn error(source: &str) -> String {
+        modules::load_str("test.txt", source).err().unwrap()[0].message.clone()
+    }
+
+    const COUNT_DOWN: &str = "\
+macro count($n) {
+    var $i
+    $i = $n
+    #top
+    output $i
+    $i = $i - $1
+    jump #top, $i > $0
+}
+#main
+    count $2
+    count $3
+    halt
+";
+
+    // Each use has its own `$i` and `#top`, named after the line it is on.
+    #[test]
+    fn expansions_on_different_lines_are_separate() {
+        let source = modules::load_str("test.txt", COUNT_DOWN).unwrap();
+        let declared: Vec<&str> = source.statements.iter().filter_map(|s| s.declares()).collect();
+        for name in ["_count_10_i", "_count_10_top", "_count_11_i", "_count_11_top"] {
+            assert!(declared.contains(&name), "{} not in {:?}", name, declared);
+        }
+        assert_eq!(output(COUNT_DOWN, &[]), [2, 1, 3, 2, 1]);
+    }
+
+    #[test]
+    fn macros_cant_use_themselves() {
+        assert_eq!(error("macro m() {\n    m\n}\n#main\n    m\n    halt\n"), "macro `m` uses itself");
+        assert_eq!(error("macro a() {\n    b\n}\nmacro b() {\n    a\n}\n#main\n    a\n    halt\n"), "macro `a` uses itself");
+    }
+
+    // The skipcond skips the whole expansion, not just its first line.
+    #[test]
+    fn skipcond_before_an_expansion() {
+        let source = "\
+macro twice($v) {
+    output $v
+    output $v
+}
+#main
+    input
+    skipcond 400
+    twice $7
+    output $1
+    halt
+";
+        assert_eq!(output(source, &[5]), [7, 7, 1]);
+        assert_eq!(output(source, &[0]), [1]);
+    }
+}
@@ -1,3 +1,352 @@
+// The command line. Everything it does goes through the library; this only reads the options, prints
+// what comes back and turns errors into an exit status.
+
+use std::collections::HashMap;
+
+use marie_compiler::diagnostic::Diagnostic;
+use marie_compiler::registry::Registry;
+use marie_compiler::{assembler, debugger, disassembler, formats, listing, modules, native, passes, peephole, profile, simulator, sourcemap, testing};
+use marie_compiler::{assertion_message, back_end, front_end, read_file, Program, Target};
+
+fn fail(diagnostics: &[Diagnostic]) -> ! {
+    for diagnostic in diagnostics.iter() {
+        eprintln!("{}", diagnostic);
+    }
+    std::process::exit(1);
+}
+
+#[derive(Debug, Default, PartialEq)]
+enum Command {
+    #[default]
+    Compile,
+    // Compile, then execute on the simulator.
+    Run,
+    // Compile, then step through the program interactively.
+    Debug,
+    // Assemble MARIE assembly into machine words.
+    Assemble,
+    // Turn machine words back into MARIE assembly.
+    Disassemble,
+    // Compile and run every program in a directory, checking their output.
+    Test,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ProfileFormat {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    command: Command,
+    input: String,
+    step_limit: u64,
+    profile: Option<ProfileFormat>,
+    symbols: Option<String>,
+    // Machine-code format to write instead of assembly text.
+    emit: Option<formats::Format>,
+    passes: passes::PassManager,
+    stats: bool,
+    cfg_dot: Option<String>,
+    listing: Option<String>,
+    source_map: Option<String>,
+    print_ir: bool,
+    // Leave out asserts.
+    release: bool,
+    target: Target,
+}
+
+impl Options {
+    fn from_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            input: "sample.txt".to_string(),
+            step_limit: 1_000_000,
+            ..Options::default()
+        };
+
+        let mut args = args.peekable();
+        match args.peek().map(String::as_str) {
+            Some("run") => options.command = Command::Run,
+            Some("debug") => options.command = Command::Debug,
+            Some("asm") => options.command = Command::Assemble,
+            Some("disasm") => options.command = Command::Disassemble,
+            Some("test") => options.command = Command::Test,
+            _ => {}
+        }
+        if options.command != Command::Compile {
+            args.next();
+        }
+
+        while let Some(arg) = args.next() {
+            if let Some(level) = passes::Level::from_flag(&arg) {
+                options.passes.level = level;
+            }
+            else if let Some(pass) = arg.strip_prefix("--disable-pass=") {
+                options.passes.disabled.push(pass_name(pass)?);
+            }
+            else if let Some(pass) = arg.strip_prefix("--print-after=") {
+                options.passes.print_after.push(if pass == "all" { pass.to_string() } else { pass_name(pass)? });
+            }
+            else if arg == "--stats" {
+                options.stats = true;
+            }
+            // Older spellings of --disable-pass.
+            else if arg == "--no-peephole" {
+                options.passes.disabled.push("peephole".to_string());
+            }
+            else if arg == "--keep-unused" {
+                options.passes.disabled.push("unused".to_string());
+            }
+            else if arg == "--keep-dead-code" {
+                options.passes.disabled.push("dce".to_string());
+            }
+            else if arg == "--keep-redundant" {
+                options.passes.disabled.push("redundant".to_string());
+            }
+            else if let Some(rule) = arg.strip_prefix("--disable-rule=") {
+                if peephole::find_rule(rule).is_none() {
+                    return Err(format!("unknown peephole rule `{}`", rule));
+                }
+                options.passes.peephole.disabled.push(rule.to_string());
+            }
+            else if arg == "--peephole-report" {
+                options.passes.peephole_report = true;
+            }
+            else if let Some(path) = path_value(&arg, "--cfg-dot", &mut args)? {
+                options.cfg_dot = Some(path);
+            }
+            else if let Some(path) = path_value(&arg, "--listing", &mut args)? {
+                options.listing = Some(path);
+            }
+            else if let Some(path) = path_value(&arg, "--source-map", &mut args)? {
+                options.source_map = Some(path);
+            }
+            else if let Some(limit) = arg.strip_prefix("--step-limit=") {
+                options.step_limit = limit.parse().map_err(|_| format!("invalid step limit `{}`", limit))?;
+            }
+            else if arg == "--profile" || arg == "--profile=table" {
+                options.command = Command::Run;
+                options.profile = Some(ProfileFormat::Table);
+            }
+            else if arg == "--profile=json" {
+                options.command = Command::Run;
+                options.profile = Some(ProfileFormat::Json);
+            }
+            else if let Some(format) = arg.strip_prefix("--emit=") {
+                options.emit = match format {
+                    "asm" => None,
+                    _ => Some(formats::Format::from_name(format).ok_or_else(|| {
+                        format!("unknown output format `{}`, expected asm, hex, logisim, ihex or readmemh", format)
+                    })?),
+                };
+            }
+            else if let Some(path) = path_value(&arg, "--symbols", &mut args)? {
+                options.symbols = Some(path);
+            }
+            else if arg == "--print-ir" {
+                options.print_ir = true;
+            }
+            else if arg == "--release" {
+                options.release = true;
+            }
+            else if let Some(name) = arg.strip_prefix("--target=") {
+                options.target = Target::from_name(name)
+                    .ok_or_else(|| format!("unknown target `{}`, expected classic or mariejs", name))?;
+            }
+            else if arg.starts_with("-") {
+                return Err(format!("unknown option `{}`", arg));
+            }
+            else {
+                options.input = arg;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+// The path of `--name=path` or `--name path`, or none when the argument is another option.
+fn path_value(arg: &str, name: &str, args: &mut impl Iterator<Item = String>) -> Result<Option<String>, String> {
+    if let Some(path) = arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+        return Ok(Some(path.to_string()));
+    }
+    if arg != name {
+        return Ok(None);
+    }
+    args.next().map(Some).ok_or(format!("`{}` needs a file name", name))
+}
+
+fn pass_name(name: &str) -> Result<String, String> {
+    if passes::PASS_NAMES.contains(&name) {
+        Ok(name.to_string())
+    }
+    else {
+        Err(format!("unknown pass `{}`, expected one of {}", name, passes::PASS_NAMES.join(", ")))
+    }
+}
+
 fn main() {
-    println!("Hello, world!");
-}
\ No newline at end of file
+    let options = match Options::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            std::process::exit(2);
+        }
+    };
+
+    if options.command == Command::Test {
+        test(options);
+        return;
+    }
+
+    match options.command {
+        Command::Compile => {
+            let source = load(&options);
+            let prog = compile_or_exit(&source, &options);
+            match options.emit {
+                Some(format) => {
+                    let image = native::assemble(&prog.native()).unwrap_or_else(|errors| fail(&errors));
+                    print!("{}", formats::write(format, &image));
+                }
+                None => print!("{}", prog),
+            }
+        }
+        Command::Run => {
+            let source = load(&options);
+            run(&compile_or_exit(&source, &options), &options, &source);
+        }
+        Command::Debug => {
+            let source = load(&options);
+            let prog = compile_or_exit(&source, &options);
+            let lines = prog.native();
+            let image = native::assemble(&lines).unwrap_or_else(|errors| fail(&errors));
+            debugger::Debugger::new(&prog, image, lines, &source, options.step_limit).repl();
+        }
+        Command::Assemble => {
+            let image = assembler::assemble(&read_input(&options)).unwrap_or_else(|errors| fail(&errors));
+            print!("{}", formats::write(options.emit.unwrap_or(formats::Format::Hex), &image));
+        }
+        Command::Test => unreachable!("handled before reading the input"),
+        Command::Disassemble => {
+            let words = formats::read(&read_input(&options)).unwrap_or_else(|errors| fail(&errors));
+            let symbols = match &options.symbols {
+                Some(path) => {
+                    let table = read_file(path)
+                        .unwrap_or_else(|e| fail(&[Diagnostic::error(format!("can't read {}: {}", path, e))]));
+                    disassembler::read_symbols(&table).unwrap_or_else(|errors| fail(&errors))
+                }
+                None => HashMap::new(),
+            };
+            for line in disassembler::disassemble(&words, &symbols) {
+                println!("{}", line);
+            }
+        }
+    }
+}
+
+// Prints a line for every program and a summary, failing when any of them did.
+fn test(options: Options) {
+    let config = testing::Config {
+        passes: options.passes,
+        release: options.release,
+        target: options.target,
+        step_limit: options.step_limit,
+    };
+    let outcomes = testing::run_directory(&options.input, &config).unwrap_or_else(|message| fail(&[Diagnostic::error(message)]));
+
+    let mut failed = 0;
+    for (name, outcome) in outcomes.iter() {
+        match outcome {
+            Ok(()) => println!("PASS {}", name),
+            Err(reason) => {
+                failed += 1;
+                print!("FAIL {}: {}", name, reason);
+                if !reason.ends_with('\n') {
+                    println!();
+                }
+            }
+        }
+    }
+
+    println!("\n{} passed, {} failed", outcomes.len() - failed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn read_input(options: &Options) -> Vec<String> {
+    read_file(&options.input)
+        .unwrap_or_else(|e| fail(&[Diagnostic::error(format!("can't read {}: {}", options.input, e))]))
+}
+
+fn load(options: &Options) -> modules::Source {
+    modules::load(&options.input).unwrap_or_else(|errors| fail(&errors))
+}
+
+// The whole pipeline for the command line, which reports problems and exits on errors. With --print-ir
+// the IR is printed and the compiler stops there.
+fn compile_or_exit(source: &modules::Source, options: &Options) -> Program {
+    let (mut prog, code) = front_end(source, &Registry::default(), options.release).unwrap_or_else(|errors| fail(&errors));
+
+    if options.print_ir {
+        print!("{}", code);
+        std::process::exit(0);
+    }
+
+    let (stats, warnings) = back_end(&code, &mut prog, &options.passes, options.target)
+        .unwrap_or_else(|errors| fail(&errors.into_iter().map(|e| source.place(e)).collect::<Vec<_>>()));
+    for warning in warnings.into_iter() {
+        eprintln!("{}", source.place(warning));
+    }
+    if options.stats {
+        eprint!("{}", stats);
+    }
+
+    if let Some(path) = &options.cfg_dot {
+        write_file(path, prog.cfg_dot());
+    }
+
+    if let Some(path) = &options.listing {
+        let lines = prog.native();
+        let image = native::assemble(&lines).unwrap_or_else(|errors| fail(&errors));
+        write_file(path, listing::listing(&prog, &lines, &image, source));
+    }
+
+    if let Some(path) = &options.source_map {
+        write_file(path, sourcemap::source_map(&prog, &prog.native(), source));
+    }
+
+    prog
+}
+
+fn write_file(path: &str, contents: String) {
+    if let Err(e) = std::fs::write(path, contents) {
+        fail(&[Diagnostic::error(format!("can't write {}: {}", path, e))]);
+    }
+}
+
+fn run(prog: &Program, options: &Options, source: &modules::Source) {
+    let lines = prog.native();
+    let image = native::assemble(&lines).unwrap_or_else(|errors| fail(&errors));
+    let mut machine = simulator::Machine::new(&image);
+    let result = machine.run(&mut simulator::StdIo::default(), options.step_limit);
+
+    // The profile goes to stderr so it doesn't mix with the program's output, and is written even
+    // when the run fails, which is when it is most useful.
+    if let Some(format) = options.profile {
+        let profile = profile::Profile::collect(&machine, &image, &lines);
+        match format {
+            ProfileFormat::Table => eprint!("{}", profile.table(source)),
+            ProfileFormat::Json => eprint!("{}", profile.json(source)),
+        }
+    }
+
+    if let Err(fault) = result {
+        fail(&[Diagnostic::error(fault.to_string())]);
+    }
+    if let Some(line) = machine.failed_assertion(&image) {
+        fail(&[source.place(Diagnostic::error(assertion_message(line, &source.lines)).at(line))]);
+    }
+}
+
@@ -0,0 +1,330 @@
+// File inclusion. `include "path"` loads another file as a module, with the path taken relative to the
+// including file. A module is named by a `module name` line at its top, or else after its file. Its
+// names are its own: they become `module__name` in the compiled program, and only the ones marked `pub`
+// can be used outside it, as `$module.name` or `#module.name`. The main file's names are left alone.
+//
+// A module's statements take the place of the first include of it, later includes only make its names
+// visible. The lines of every file are kept one after another, the main file first, along with the
+// lines macros expand into, so statement lines index into one list of source lines; `place` turns such
+// a line back into a file and line.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::ast::{self, Operand, Statement, StatementKind};
+use crate::diagnostic::Diagnostic;
+use crate::macros::{self, Expansion};
+use crate::read_file;
+
+pub struct Source {
+    pub lines: Vec<String>,
+    pub statements: Vec<Statement>,
+    // Each file's path, how many lines come before it and how many it has.
+    files: Vec<(String, usize, usize)>,
+    expansions: HashMap<usize, Expansion>,
+}
+
+impl Source {
+    pub fn path(&self) -> &str {
+        &self.files[0].0
+    }
+
+    // Every file's path, the main file first.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|(path, _, _)| path.as_str())
+    }
+
+    // The main file's own lines.
+    pub fn main(&self) -> &[String] {
+        &self.lines[..self.files[0].2]
+    }
+
+    // The file a line belongs to and its line number there. An expanded line is the macro body's line.
+    pub fn locate(&self, line: usize) -> (&str, usize) {
+        match self.expansions.get(&line) {
+            Some(expansion) => self.locate(expansion.body),
+            None => {
+                let (file, start, _) = self.files.iter().find(|(_, start, len)| line > *start && line <= start + len).unwrap_or(&self.files[0]);
+                (file, line - start)
+            }
+        }
+    }
+
+    // Where a line is, for people: `path:line` in an included file, just the line in the main file, as
+    // diagnostics have it.
+    pub fn position(&self, line: usize) -> String {
+        match self.locate(line) {
+            (file, local) if file != self.path() => format!("{}:{}", file, local),
+            (_, local) => local.to_string(),
+        }
+    }
+
+    // The text of a line, without the indentation.
+    pub fn text(&self, line: usize) -> &str {
+        line.checked_sub(1).and_then(|i| self.lines.get(i)).map(|s| s.trim()).unwrap_or_default()
+    }
+
+    // Ties a diagnostic to the file its line is in, with a note for each macro use it is expanded from.
+    pub fn place(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        let Some(mut line) = diagnostic.line else { return diagnostic };
+        let mut notes = Vec::new();
+        while let Some(expansion) = self.expansions.get(&line) {
+            notes.push(Diagnostic::note(format!("in `{}` used here", expansion.name)).at(expansion.call));
+            line = expansion.body;
+        }
+
+        let (file, local) = self.locate(line);
+        if file != self.path() {
+            diagnostic.file = Some(file.to_string());
+        }
+        diagnostic.line = Some(local);
+        // A stretch that ends in another file or macro is only reported by its first line.
+        diagnostic.end = diagnostic.end.map(|end| self.locate(end)).filter(|(f, end)| *f == file && *end > local).map(|(_, end)| end);
+        diagnostic.notes = notes.into_iter().chain(diagnostic.notes).map(|n| self.place(n)).collect();
+        diagnostic
+    }
+}
+
+fn mangle(module: &str, name: &str) -> String {
+    format!("{}__{}", module, name)
+}
+
+struct Module {
+    name: String,
+    public: HashSet<String>,
+}
+
+// How names are resolved inside one file.
+struct Scope<'a> {
+    module: Option<&'a str>,
+    declared: HashSet<&'a str>,
+    // Public names of the modules this file includes.
+    visible: HashMap<String, &'a HashSet<String>>,
+}
+
+impl Scope<'_> {
+    fn resolve(&self, name: &str) -> Result<String, String> {
+        if let Some((module, item)) = name.split_once('.') {
+            if Some(module) == self.module {
+                return self.resolve(item);
+            }
+            let public = self.visible.get(module).ok_or(format!("module `{}` is not included here", module))?;
+            if !public.contains(item) {
+                return Err(format!("`{}` is not public in module `{}`", item, module));
+            }
+            return Ok(mangle(module, item));
+        }
+
+        match self.module {
+            Some(module) if self.declared.contains(name) => Ok(mangle(module, name)),
+            Some(module) => Err(format!("`{}` is not declared in module `{}`", name, module)),
+            None => Ok(name.to_string()),
+        }
+    }
+
+    fn operand(&self, operand: &Operand) -> Result<Operand, String> {
+        Ok(match operand {
+            Operand::Var(name) => Operand::Var(self.resolve(name)?),
+            Operand::Pointer(name) => Operand::Pointer(self.resolve(name)?),
+            Operand::Address(name) => Operand::Address(self.resolve(name)?),
+            Operand::Index(name, offset) => Operand::Index(self.resolve(name)?, Box::new(self.operand(offset)?)),
+            Operand::Element(name, offset) => Operand::Element(self.resolve(name)?, Box::new(self.operand(offset)?)),
+            Operand::Indirect(name, depth) => Operand::Indirect(self.resolve(name)?, *depth),
+            Operand::Cell(name, row, column) => {
+                Operand::Cell(self.resolve(name)?, Box::new(self.operand(row)?), Box::new(self.operand(column)?))
+            }
+            Operand::Deref(address) => Operand::Deref(Box::new(self.operand(address)?)),
+            Operand::Label(name) => Operand::Label(self.resolve(name)?),
+            Operand::Number(_) | Operand::Code(_) | Operand::Relation(_) => operand.clone(),
+        })
+    }
+
+    fn declaration(&self, name: &str) -> Result<String, String> {
+        if name.contains('.') {
+            return Err(format!("can't declare the qualified name `{}`", name));
+        }
+        self.resolve(name)
+    }
+
+    fn statement(&self, stmt: &Statement) -> Result<Statement, String> {
+        let kind = match &stmt.kind {
+            StatementKind::Var { name, value } => StatementKind::Var { name: self.declaration(name)?, value: *value },
+            StatementKind::Const { name, value } => StatementKind::Const { name: self.declaration(name)?, value: *value },
+            StatementKind::Array { name, dims } => StatementKind::Array { name: self.declaration(name)?, dims: dims.clone() },
+            StatementKind::Label(name) => StatementKind::Label(self.declaration(name)?),
+            StatementKind::Instruction { mnemonic, operands } => StatementKind::Instruction {
+                mnemonic: mnemonic.clone(),
+                operands: operands.iter().map(|o| self.operand(o)).collect::<Result<_, _>>()?,
+            },
+            StatementKind::Assign { dest, lhs, rhs } => StatementKind::Assign {
+                dest: self.operand(dest)?,
+                lhs: self.operand(lhs)?,
+                rhs: rhs.as_ref().map(|(op, rhs)| Ok::<_, String>((*op, self.operand(rhs)?))).transpose()?,
+            },
+            StatementKind::Asm { label, instruction, operand } => StatementKind::Asm {
+                label: label.as_deref().map(|l| self.declaration(l)).transpose()?,
+                instruction: *instruction,
+                operand: operand.as_ref().map(|o| self.operand(o)).transpose()?,
+            },
+            kind => kind.clone(),
+        };
+        Ok(Statement { kind, ..stmt.clone() })
+    }
+}
+
+#[derive(Default)]
+struct Loader {
+    lines: Vec<String>,
+    files: Vec<(String, usize, usize)>,
+    expansions: HashMap<usize, Expansion>,
+    modules: HashMap<PathBuf, Module>,
+    // Files being loaded, innermost last, to catch an include cycle.
+    stack: Vec<(PathBuf, String)>,
+    errors: Vec<Diagnostic>,
+}
+
+impl Loader {
+    // Loads one file, the main one when `included` is false, returning its statements with those of the
+    // modules it includes for the first time spliced in.
+    fn load(&mut self, path: &Path, canonical: PathBuf, included: bool) -> Result<Vec<Statement>, String> {
+        let display = path.display().to_string();
+        let lines = read_file(&display).map_err(|e| format!("can't read {}: {}", display, e))?;
+        self.load_lines(path, canonical, included, lines)
+    }
+
+    fn load_lines(&mut self, path: &Path, canonical: PathBuf, included: bool, lines: Vec<String>) -> Result<Vec<Statement>, String> {
+        let display = path.display().to_string();
+        let offset = self.lines.len();
+        self.files.push((display.clone(), offset, lines.len()));
+        self.lines.extend(lines.iter().cloned());
+
+        let mut statements = match ast::parse(&lines) {
+            Ok(statements) => statements,
+            Err(errors) => {
+                self.errors.extend(errors.into_iter().map(|e| {
+                    let line = e.line.map(|l| l + offset);
+                    Diagnostic { line, end: e.end.map(|l| l + offset), ..e }
+                }));
+                return Ok(Vec::new());
+            }
+        };
+        for stmt in statements.iter_mut() {
+            stmt.line += offset;
+        }
+        let statements = match macros::expand(statements, &mut self.lines, &mut self.expansions) {
+            Ok(statements) => statements,
+            Err(errors) => {
+                self.errors.extend(errors);
+                return Ok(Vec::new());
+            }
+        };
+
+        let name = match statements.first().map(|s| &s.kind) {
+            Some(StatementKind::Module(name)) => name.clone(),
+            _ => path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default(),
+        };
+        for stmt in statements.iter().skip(1) {
+            if let StatementKind::Module(_) = stmt.kind {
+                self.errors.push(Diagnostic::error("`module` has to be the first statement").at(stmt.line));
+            }
+        }
+        if included {
+            if !ast::valid_name(&name) {
+                return Err(format!("`{}` is not a valid module name, name it with `module`", name));
+            }
+            if let Some((other, _)) = self.modules.iter().find(|(_, m)| m.name == name) {
+                return Err(format!("module `{}` is already defined by {}", name, other.display()));
+            }
+        }
+
+        self.stack.push((canonical.clone(), display));
+        let mut spliced = HashMap::new();
+        let mut visible = Vec::new();
+        for (i, stmt) in statements.iter().enumerate() {
+            if let StatementKind::Include(target) = &stmt.kind {
+                match self.include(path, target) {
+                    Ok((module, statements)) => {
+                        visible.push(module);
+                        spliced.insert(i, statements);
+                    }
+                    Err(message) => self.errors.push(Diagnostic::error(message).at(stmt.line)),
+                }
+            }
+        }
+        self.stack.pop();
+
+        let module = included.then_some(name.as_str());
+        let scope = Scope {
+            module,
+            declared: statements.iter().filter_map(|s| s.declares()).collect(),
+            visible: visible
+                .iter()
+                .filter_map(|p| self.modules.get(p))
+                .map(|m| (m.name.clone(), &m.public))
+                .collect(),
+        };
+
+        let mut result = Vec::new();
+        let mut errors = Vec::new();
+        for (i, stmt) in statements.iter().enumerate() {
+            match &stmt.kind {
+                StatementKind::Include(_) => result.extend(spliced.remove(&i).unwrap_or_default()),
+                StatementKind::Module(_) => {}
+                _ => match scope.statement(stmt) {
+                    Ok(stmt) => result.push(stmt),
+                    Err(message) => errors.push(Diagnostic::error(message).at(stmt.line)),
+                },
+            }
+        }
+        self.errors.extend(errors);
+
+        if included {
+            let public = statements.iter().filter(|s| s.public).filter_map(|s| s.declares()).map(String::from).collect();
+            self.modules.insert(canonical, Module { name, public });
+        }
+        Ok(result)
+    }
+
+    // Resolves an include, returning the module's path and, the first time it is included, its
+    // statements.
+    fn include(&mut self, from: &Path, target: &str) -> Result<(PathBuf, Vec<Statement>), String> {
+        let path = from.parent().unwrap_or(Path::new("")).join(target);
+        let canonical = path.canonicalize().map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+
+        if let Some(k) = self.stack.iter().position(|(p, _)| *p == canonical) {
+            let cycle: Vec<&str> = self.stack[k..].iter().map(|(_, d)| d.as_str()).collect();
+            return Err(format!("include cycle: {} -> {}", cycle.join(" -> "), cycle[0]));
+        }
+        if self.modules.contains_key(&canonical) {
+            return Ok((canonical, Vec::new()));
+        }
+        let statements = self.load(&path, canonical.clone(), true)?;
+        Ok((canonical, statements))
+    }
+}
+
+// Loads a program and everything it includes.
+pub fn load(path: &str) -> Result<Source, Vec<Diagnostic>> {
+    let lines = read_file(path).map_err(|e| vec![Diagnostic::error(format!("can't read {}: {}", path, e))])?;
+    load_lines(path, lines)
+}
+
+// Loads a program given as text. Its includes are taken relative to `path`, which needn't exist.
+pub fn load_str(path: &str, text: &str) -> Result<Source, Vec<Diagnostic>> {
+    load_lines(path, text.lines().map(String::from).collect())
+}
+
+fn load_lines(path: &str, lines: Vec<String>) -> Result<Source, Vec<Diagnostic>> {
+    let mut loader = Loader::default();
+    let main = Path::new(path);
+    let canonical = main.canonicalize().unwrap_or_else(|_| main.to_path_buf());
+    let statements = loader.load_lines(main, canonical, false, lines).map_err(|message| vec![Diagnostic::error(message)])?;
+
+    let source = Source { lines: loader.lines, statements, files: loader.files, expansions: loader.expansions };
+    if loader.errors.is_empty() {
+        Ok(source)
+    }
+    else {
+        Err(loader.errors.into_iter().map(|e| source.place(e)).collect())
+    }
+}
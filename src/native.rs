@@ -0,0 +1,219 @@
+// MARIE assembly in structured form. The compiler's output is a list of `Line`s, which prints as the
+// textual assembly and assembles into a memory image of 16-bit words.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::diagnostic::Diagnostic;
+use crate::Label;
+
+pub const MEMORY_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeInstruction {
+    Jns, Load, Store, Add, Subt, Input, Output, Halt, Skipcond, Jump, Clear, Addi, Jumpi, Loadi, Storei,
+}
+
+impl NativeInstruction {
+    pub(crate) fn from_str(s: &str) -> Option<NativeInstruction> {
+        use NativeInstruction::*;
+        match s.to_ascii_lowercase().as_str() {
+            "jns" => Some(Jns),
+            "load" => Some(Load),
+            "store" => Some(Store),
+            "add" => Some(Add),
+            "subt" => Some(Subt),
+            "input" => Some(Input),
+            "output" => Some(Output),
+            "halt" => Some(Halt),
+            "skipcond" => Some(Skipcond),
+            "jump" => Some(Jump),
+            "clear" => Some(Clear),
+            "addi" => Some(Addi),
+            "jumpi" => Some(Jumpi),
+            "loadi" => Some(Loadi),
+            "storei" => Some(Storei),
+            _ => None
+        }
+    }
+
+    pub fn opcode(self) -> u16 {
+        self as u16
+    }
+
+    pub fn from_opcode(opcode: u16) -> Option<NativeInstruction> {
+        use NativeInstruction::*;
+        [Jns, Load, Store, Add, Subt, Input, Output, Halt, Skipcond, Jump, Clear, Addi, Jumpi, Loadi, Storei]
+            .get(opcode as usize)
+            .copied()
+    }
+
+    pub fn has_operand(self) -> bool {
+        use NativeInstruction::*;
+        !matches!(self, Input | Output | Halt | Clear)
+    }
+
+    pub fn name(self) -> &'static str {
+        use NativeInstruction::*;
+        match self {
+            Jns => "jns",
+            Load => "load",
+            Store => "store",
+            Add => "add",
+            Subt => "subt",
+            Input => "input",
+            Output => "output",
+            Halt => "halt",
+            Skipcond => "skipcond",
+            Jump => "jump",
+            Clear => "clear",
+            Addi => "addi",
+            Jumpi => "jumpi",
+            Loadi => "loadi",
+            Storei => "storei",
+        }
+    }
+}
+
+impl fmt::Display for NativeInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Symbol(Label),
+    // Written in hex, as MARIE does for skipcond.
+    Number(u16),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Symbol(name) => f.write_str(name),
+            // A leading letter would read back as a label.
+            Operand::Number(n) if *n >= 0xA00 => write!(f, "0{:03X}", n),
+            Operand::Number(n) => write!(f, "{:03X}", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineKind {
+    Instruction(NativeInstruction, Option<Operand>),
+    Dec(i16),
+    Hex(u16),
+}
+
+impl fmt::Display for LineKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineKind::Instruction(instruction, Some(operand)) => write!(f, "{} {}", instruction, operand),
+            LineKind::Instruction(instruction, None) => write!(f, "{}", instruction),
+            LineKind::Dec(value) => write!(f, "dec {}", value),
+            LineKind::Hex(value) => write!(f, "hex {:X}", value),
+        }
+    }
+}
+
+// One word of the program: the line assembles to the word at the next address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub label: Option<Label>,
+    pub kind: LineKind,
+    pub source: Option<usize>,
+}
+
+impl Line {
+    pub fn new(kind: LineKind) -> Line {
+        Line { label: None, kind, source: None }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.label {
+            Some(label) => write!(f, "{},\t{}", label, self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub words: Vec<u16>,
+    pub symbols: HashMap<Label, u16>,
+    // Source line of the word at each address.
+    pub lines: Vec<Option<usize>>,
+}
+
+// Lays the lines out from address 0, one word each.
+pub fn assemble(lines: &[Line]) -> Result<Image, Vec<Diagnostic>> {
+    let placed: Vec<(usize, &Line)> = lines.iter().enumerate().collect();
+    place(&placed)
+}
+
+fn error(line: &Line, message: String) -> Diagnostic {
+    let diagnostic = Diagnostic::error(message);
+    match line.source {
+        Some(source) => diagnostic.at(source),
+        None => diagnostic,
+    }
+}
+
+// Builds the image from lines at the given addresses, collecting label addresses first so operands can
+// refer forward. Addresses nothing is placed at hold zero.
+pub fn place(lines: &[(usize, &Line)]) -> Result<Image, Vec<Diagnostic>> {
+    let mut errors = Vec::new();
+    let mut symbols = HashMap::new();
+    let mut used = HashSet::new();
+    for &(address, line) in lines.iter() {
+        if address >= MEMORY_SIZE {
+            errors.push(error(line, format!("address {:X} is outside memory, which ends at {:X}", address, MEMORY_SIZE - 1)));
+        }
+        else if !used.insert(address) {
+            errors.push(error(line, format!("address {:X} is already taken by an earlier line", address)));
+        }
+        if let Some(label) = &line.label {
+            if symbols.insert(label.clone(), address as u16).is_some() {
+                errors.push(error(line, format!("label `{}` defined twice", label)));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let size = lines.iter().map(|&(address, _)| address + 1).max().unwrap_or(0);
+    let mut words = vec![0; size];
+    let mut sources = vec![None; size];
+    for &(address, line) in lines.iter() {
+        words[address] = match &line.kind {
+            LineKind::Instruction(instruction, operand) => {
+                let target = match operand {
+                    Some(Operand::Symbol(name)) => symbols.get(name).copied().unwrap_or_else(|| {
+                        errors.push(error(line, format!("undefined label `{}`", name)));
+                        0
+                    }),
+                    Some(Operand::Number(n)) if *n as usize >= MEMORY_SIZE => {
+                        errors.push(error(line, format!("operand {:X} is out of range, addresses end at {:X}", n, MEMORY_SIZE - 1)));
+                        0
+                    }
+                    Some(Operand::Number(n)) => *n,
+                    None => 0,
+                };
+                instruction.opcode() << 12 | target
+            }
+            LineKind::Dec(value) => *value as u16,
+            LineKind::Hex(value) => *value,
+        };
+        sources[address] = line.source;
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(Image { words, symbols, lines: sources })
+}
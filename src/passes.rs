@@ -0,0 +1,204 @@
+// Pass manager. Every optimisation is a `Pass` over the whole Program; an optimisation level picks
+// which of them run and in what order, and the manager can dump the code after a pass and count what
+// each one removed.
+
+use std::fmt;
+
+use crate::diagnostic::Diagnostic;
+use crate::{dce, peephole, redundant, unused, Program};
+
+pub trait Pass {
+    fn name(&self) -> &'static str;
+
+    // Runs the pass, returning any warnings for the user.
+    fn run(&mut self, prog: &mut Program) -> Vec<Diagnostic>;
+}
+
+pub struct DeadCode;
+
+impl Pass for DeadCode {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn run(&mut self, prog: &mut Program) -> Vec<Diagnostic> {
+        dce::remove_unreachable(prog)
+    }
+}
+
+pub struct Redundant;
+
+impl Pass for Redundant {
+    fn name(&self) -> &'static str {
+        "redundant"
+    }
+
+    fn run(&mut self, prog: &mut Program) -> Vec<Diagnostic> {
+        redundant::remove_redundant(prog);
+        Vec::new()
+    }
+}
+
+pub struct Peephole {
+    pub config: peephole::Config,
+    pub report: bool,
+}
+
+impl Pass for Peephole {
+    fn name(&self) -> &'static str {
+        "peephole"
+    }
+
+    fn run(&mut self, prog: &mut Program) -> Vec<Diagnostic> {
+        let report = peephole::optimise(prog, &self.config);
+        if self.report {
+            eprint!("{}", report);
+        }
+        Vec::new()
+    }
+}
+
+pub struct UnusedData;
+
+impl Pass for UnusedData {
+    fn name(&self) -> &'static str {
+        "unused"
+    }
+
+    fn run(&mut self, prog: &mut Program) -> Vec<Diagnostic> {
+        let dropped = unused::remove_unused(prog);
+        if dropped.is_empty() {
+            Vec::new()
+        }
+        else {
+            vec![Diagnostic::warning(format!("dropped unused data words: {}", dropped.join(", ")))]
+        }
+    }
+}
+
+pub const PASS_NAMES: [&str; 4] = ["dce", "redundant", "peephole", "unused"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Level {
+    O0,
+    O1,
+    #[default]
+    O2,
+    Os,
+}
+
+impl Level {
+    pub fn from_flag(flag: &str) -> Option<Level> {
+        match flag {
+            "-O0" => Some(Level::O0),
+            "-O1" => Some(Level::O1),
+            "-O2" => Some(Level::O2),
+            "-Os" => Some(Level::Os),
+            _ => None,
+        }
+    }
+
+    // Names of the passes the level runs, in order.
+    fn pipeline(self) -> &'static [&'static str] {
+        match self {
+            Level::O0 => &[],
+            Level::O1 => &["dce", "peephole", "unused"],
+            Level::O2 | Level::Os => &["dce", "redundant", "peephole", "unused"],
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    // Pass name with the code and data words it removed.
+    pub removed: Vec<(&'static str, isize, isize)>,
+    pub before: (usize, usize),
+    pub after: (usize, usize),
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<12} {:>12} {:>12}", "pass", "code removed", "data removed")?;
+        for (name, code, data) in self.removed.iter() {
+            writeln!(f, "{:<12} {:>12} {:>12}", name, code, data)?;
+        }
+        writeln!(
+            f,
+            "total: {} -> {} code words, {} -> {} data words",
+            self.before.0, self.after.0, self.before.1, self.after.1
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PassManager {
+    pub level: Level,
+    pub disabled: Vec<String>,
+    pub print_after: Vec<String>,
+    pub peephole: peephole::Config,
+    pub peephole_report: bool,
+}
+
+impl PassManager {
+    fn create(&self, name: &str) -> Box<dyn Pass> {
+        match name {
+            "dce" => Box::new(DeadCode),
+            "redundant" => Box::new(Redundant),
+            "peephole" => Box::new(Peephole { config: self.peephole.clone(), report: self.peephole_report }),
+            "unused" => Box::new(UnusedData),
+            _ => unreachable!("unknown pass `{}`", name),
+        }
+    }
+
+    fn passes(&self) -> Vec<Box<dyn Pass>> {
+        self.level
+            .pipeline()
+            .iter()
+            .filter(|name| !self.disabled.iter().any(|d| d == *name))
+            .map(|name| self.create(name))
+            .collect()
+    }
+
+    fn dump(&self, name: &str, prog: &Program) {
+        if self.print_after.iter().any(|p| p == name || p == "all") {
+            eprintln!("; after {}", name);
+            for (instruction, line) in prog.instructions.iter().zip(prog.lines.iter()) {
+                for native in instruction.to_native().lines() {
+                    eprintln!("{:>5}  {}", line, native);
+                }
+            }
+        }
+    }
+
+    // Runs the level's pipeline. -Os repeats it for as long as the program keeps shrinking.
+    pub fn run(&self, prog: &mut Program) -> (Stats, Vec<Diagnostic>) {
+        let mut stats = Stats {
+            before: sizes(prog),
+            ..Stats::default()
+        };
+        let mut warnings = Vec::new();
+
+        loop {
+            let size = sizes(prog);
+            for mut pass in self.passes() {
+                let (code, data) = sizes(prog);
+                warnings.extend(pass.run(prog));
+                let after = sizes(prog);
+                stats.removed.push((pass.name(), code as isize - after.0 as isize, data as isize - after.1 as isize));
+                self.dump(pass.name(), prog);
+            }
+
+            if self.level != Level::Os || sizes(prog) == size {
+                break;
+            }
+        }
+
+        stats.after = sizes(prog);
+        (stats, warnings)
+    }
+}
+
+// Code and data words, counting every word of an array.
+fn sizes(prog: &Program) -> (usize, usize) {
+    (prog.code_size(), prog.data_size() as usize)
+}
@@ -0,0 +1,277 @@
+// Peephole optimisation: slides a small window over the instruction list and rewrites
+// sequences the handlers emit naively into cheaper equivalents.
+
+use std::fmt;
+
+use crate::{Program, Reference, ReferenceType, SimpleInstruction};
+
+type Rewrite = fn(&[SimpleInstruction]) -> Option<Vec<SimpleInstruction>>;
+
+pub struct Rule {
+    pub name: &'static str,
+    pub window: usize,
+    pub(crate) rewrite: Rewrite,
+}
+
+pub const RULES: &[Rule] = &[
+    Rule { name: "store-load", window: 2, rewrite: store_load },
+    Rule { name: "load-store", window: 2, rewrite: load_store },
+    Rule { name: "dead-acc", window: 2, rewrite: dead_acc },
+    Rule { name: "jump-next", window: 2, rewrite: jump_next },
+    Rule { name: "load-zero", window: 1, rewrite: load_zero },
+    Rule { name: "add-zero", window: 1, rewrite: add_zero },
+];
+
+pub fn find_rule(name: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|r| r.name == name)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub disabled: Vec<String>,
+}
+
+impl Config {
+    fn rules(&self) -> Vec<&'static Rule> {
+        RULES
+            .iter()
+            .filter(|r| !self.disabled.iter().any(|d| d == r.name))
+            .collect()
+    }
+}
+
+// How many times each enabled rule fired.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub fired: Vec<(&'static str, usize)>,
+}
+
+impl Report {
+    pub fn total(&self) -> usize {
+        self.fired.iter().map(|(_, count)| count).sum()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, count) in self.fired.iter() {
+            writeln!(f, "peephole: {:<12} fired {} time(s)", name, count)?;
+        }
+        writeln!(f, "peephole: {} rewrite(s) in total", self.total())
+    }
+}
+
+// Applies the enabled rules until none of them matches anywhere.
+pub fn optimise(prog: &mut Program, config: &Config) -> Report {
+    let rules = config.rules();
+    let mut report = Report {
+        fired: rules.iter().map(|r| (r.name, 0)).collect(),
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut i = 0;
+        while i < prog.instructions.len() {
+            let mut rewritten = false;
+            // The instruction after a skipcond has to stay a single instruction.
+            let skipped = prog.skipped(i);
+            for (k, rule) in rules.iter().enumerate().filter(|_| !skipped) {
+                let end = i + rule.window;
+                if end > prog.instructions.len() {
+                    continue;
+                }
+                if let Some(replacement) = (rule.rewrite)(&prog.instructions[i..end]) {
+                    prog.splice(i..end, replacement);
+                    report.fired[k].1 += 1;
+                    rewritten = true;
+                    break;
+                }
+            }
+
+            if rewritten {
+                changed = true;
+            }
+            else {
+                i += 1;
+            }
+        }
+    }
+
+    report
+}
+
+// Both references name the same memory word. Offsets are left alone, their expansion clobbers the accumulator.
+fn same_word(a: &Reference, b: &Reference) -> bool {
+    match (&a.reference_type, &b.reference_type) {
+        (ReferenceType::Direct, ReferenceType::Direct) | (ReferenceType::Pointer, ReferenceType::Pointer) => {
+            a.var.name == b.var.name
+        }
+        _ => false,
+    }
+}
+
+// Address words are constants too, but their value is only settled by layout.
+fn is_zero(reference: &Reference) -> bool {
+    matches!(reference.reference_type, ReferenceType::Direct)
+        && reference.var.constant
+        && reference.var.default_value == 0
+        && !reference.var.name.ends_with("_addr")
+}
+
+// store x; load x  ->  store x
+fn store_load(window: &[SimpleInstruction]) -> Option<Vec<SimpleInstruction>> {
+    use SimpleInstruction as SI;
+    match window {
+        [SI::Store(a), SI::Load(b)] if same_word(a, b) => Some(vec![window[0].clone()]),
+        _ => None,
+    }
+}
+
+// load x; store x  ->  load x
+fn load_store(window: &[SimpleInstruction]) -> Option<Vec<SimpleInstruction>> {
+    use SimpleInstruction as SI;
+    match window {
+        [SI::Load(a), SI::Store(b)] if same_word(a, b) => Some(vec![window[0].clone()]),
+        _ => None,
+    }
+}
+
+// An accumulator value that is overwritten before anything reads it:  load x; load y  ->  load y
+fn dead_acc(window: &[SimpleInstruction]) -> Option<Vec<SimpleInstruction>> {
+    use SimpleInstruction as SI;
+    match window {
+        [SI::Load(_) | SI::Add(_) | SI::Subt(_) | SI::Clear, SI::Load(_) | SI::Clear | SI::Input] => {
+            Some(vec![window[1].clone()])
+        }
+        _ => None,
+    }
+}
+
+// jump L; L,  ->  L,
+fn jump_next(window: &[SimpleInstruction]) -> Option<Vec<SimpleInstruction>> {
+    use SimpleInstruction as SI;
+    match window {
+        [SI::Jump(target), SI::Label(label)] if target == label => Some(vec![window[1].clone()]),
+        _ => None,
+    }
+}
+
+// load c_0  ->  clear
+fn load_zero(window: &[SimpleInstruction]) -> Option<Vec<SimpleInstruction>> {
+    use SimpleInstruction as SI;
+    match window {
+        [SI::Load(r)] if is_zero(r) => Some(vec![SI::Clear]),
+        _ => None,
+    }
+}
+
+// add c_0 / subt c_0  ->  nothing
+fn add_zero(window: &[SimpleInstruction]) -> Option<Vec<SimpleInstruction>> {
+    use SimpleInstruction as SI;
+    match window {
+        [SI::Add(r) | SI::Subt(r)] if is_zero(r) => Some(Vec::new()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SkipcondType, Variable};
+
+    fn var(name: &str, value: i16, constant: bool) -> Variable {
+        Variable { name: name.to_string(), default_value: value, constant, address: 1, line: None, size: 1, row_length: None }
+    }
+
+    fn direct(name: &str) -> Reference {
+        Reference::new(var(name, 0, false), ReferenceType::Direct)
+    }
+
+    fn zero() -> Reference {
+        Reference::new(var("c_0", 0, true), ReferenceType::Direct)
+    }
+
+    fn text(instructions: &[SimpleInstruction]) -> Vec<String> {
+        instructions.iter().map(|i| i.to_native()).collect()
+    }
+
+    fn rewrite(rule: &str, window: &[SimpleInstruction]) -> Option<Vec<String>> {
+        (find_rule(rule).unwrap().rewrite)(window).map(|r| text(&r))
+    }
+
+    use SimpleInstruction as SI;
+
+    #[test]
+    fn store_load() {
+        assert_eq!(rewrite("store-load", &[SI::Store(direct("x")), SI::Load(direct("x"))]), Some(vec!["store x".to_string()]));
+        assert_eq!(rewrite("store-load", &[SI::Store(direct("x")), SI::Load(direct("y"))]), None);
+    }
+
+    #[test]
+    fn load_store() {
+        assert_eq!(rewrite("load-store", &[SI::Load(direct("x")), SI::Store(direct("x"))]), Some(vec!["load x".to_string()]));
+        assert_eq!(rewrite("load-store", &[SI::Load(direct("x")), SI::Store(direct("y"))]), None);
+    }
+
+    #[test]
+    fn dead_acc() {
+        assert_eq!(rewrite("dead-acc", &[SI::Add(direct("x")), SI::Load(direct("y"))]), Some(vec!["load y".to_string()]));
+        assert_eq!(rewrite("dead-acc", &[SI::Load(direct("x")), SI::Store(direct("y"))]), None);
+    }
+
+    #[test]
+    fn jump_next() {
+        let window = [SI::Jump("L".to_string()), SI::Label("L".to_string())];
+        assert_eq!(rewrite("jump-next", &window), Some(vec!["L,".to_string()]));
+        assert_eq!(rewrite("jump-next", &[SI::Jump("L".to_string()), SI::Label("M".to_string())]), None);
+    }
+
+    #[test]
+    fn load_zero() {
+        assert_eq!(rewrite("load-zero", &[SI::Load(zero())]), Some(vec!["clear".to_string()]));
+        // A variable that happens to start at zero may change.
+        assert_eq!(rewrite("load-zero", &[SI::Load(direct("x"))]), None);
+    }
+
+    #[test]
+    fn add_zero() {
+        assert_eq!(rewrite("add-zero", &[SI::Add(zero())]), Some(Vec::new()));
+        assert_eq!(rewrite("add-zero", &[SI::Subt(zero())]), Some(Vec::new()));
+        assert_eq!(rewrite("add-zero", &[SI::Add(direct("x"))]), None);
+    }
+
+    #[test]
+    fn disabled_rules_do_not_fire() {
+        let mut prog = Program::default();
+        prog.add_instruction(SI::Store(direct("x")), 1);
+        prog.add_instruction(SI::Load(direct("x")), 1);
+        let config = Config { disabled: vec!["store-load".to_string()] };
+        let report = optimise(&mut prog, &config);
+        assert_eq!(report.total(), 0);
+        assert_eq!(text(&prog.instructions), ["store x", "load x"]);
+    }
+
+    // `load y` is what the skipcond skips, removing it would make the skipcond skip `load x` instead.
+    #[test]
+    fn instruction_after_skipcond_is_kept() {
+        let mut prog = Program::default();
+        for instruction in [SI::Load(direct("x")), SI::Skipcond(SkipcondType::Zero), SI::Load(direct("y")), SI::Load(direct("x"))] {
+            prog.add_instruction(instruction, 1);
+        }
+        optimise(&mut prog, &Config::default());
+        assert_eq!(text(&prog.instructions), ["load x", "skipcond 400", "load y", "load x"]);
+    }
+
+    // A label between them takes no memory, the skipcond still skips the jump.
+    #[test]
+    fn instruction_after_skipcond_and_label_is_kept() {
+        let mut prog = Program::default();
+        let jump = SI::Jump("L".to_string());
+        for instruction in [SI::Skipcond(SkipcondType::Zero), SI::Label("M".to_string()), jump, SI::Label("L".to_string())] {
+            prog.add_instruction(instruction, 1);
+        }
+        optimise(&mut prog, &Config::default());
+        assert_eq!(text(&prog.instructions), ["skipcond 400", "M,", "jump L", "L,"]);
+    }
+}
@@ -0,0 +1,157 @@
+// Execution profile of a simulator run. The machine counts instructions and memory accesses per address;
+// here they are tied back to the MARIE line at each address, the source line it came from and the
+// nearest label above it.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use crate::json::{escape, string};
+use crate::modules::Source;
+use crate::native::{Image, Line};
+use crate::simulator::Machine;
+
+#[derive(Debug, Clone, Default)]
+pub struct Counts {
+    pub instructions: u64,
+    pub accesses: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.instructions += other.instructions;
+        self.accesses += other.accesses;
+    }
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instructions, {} memory accesses", self.instructions, self.accesses)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InstructionCounts {
+    pub address: u16,
+    pub text: String,
+    pub line: Option<usize>,
+    pub counts: Counts,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub total: Counts,
+    pub instructions: Vec<InstructionCounts>,
+    // Sorted hottest first.
+    pub lines: Vec<(usize, Counts)>,
+    pub labels: Vec<(String, Counts)>,
+}
+
+// Labels the compiler makes up (`_L1`, `_skip2`) and data words don't start a region of their own.
+fn region_label(line: &Line) -> Option<&str> {
+    match &line.label {
+        Some(label) if line.source.is_some() && !label.starts_with('_') => Some(label),
+        _ => None,
+    }
+}
+
+fn sort(counts: &mut [(impl Ord, Counts)]) {
+    counts.sort_by(|a, b| {
+        b.1.instructions
+            .cmp(&a.1.instructions)
+            .then(b.1.accesses.cmp(&a.1.accesses))
+            .then(a.0.cmp(&b.0))
+    });
+}
+
+impl Profile {
+    pub fn collect(machine: &Machine, image: &Image, lines: &[Line]) -> Profile {
+        let mut profile = Profile::default();
+        let mut by_line: BTreeMap<usize, Counts> = BTreeMap::new();
+        let mut by_label: BTreeMap<String, Counts> = BTreeMap::new();
+        let mut label = "(entry)";
+
+        for (address, line) in lines.iter().enumerate() {
+            label = region_label(line).unwrap_or(label);
+
+            let counts = Counts { instructions: machine.executed[address], accesses: machine.accesses[address] };
+            if counts.instructions == 0 {
+                continue;
+            }
+
+            profile.total.add(&counts);
+            if let Some(source) = image.lines[address] {
+                by_line.entry(source).or_default().add(&counts);
+            }
+            by_label.entry(label.to_string()).or_default().add(&counts);
+            profile.instructions.push(InstructionCounts {
+                address: address as u16,
+                text: line.kind.to_string(),
+                line: image.lines[address],
+                counts,
+            });
+        }
+
+        profile.lines = by_line.into_iter().collect();
+        profile.labels = by_label.into_iter().collect();
+        sort(&mut profile.lines);
+        sort(&mut profile.labels);
+        profile
+    }
+
+    // The per-line table shows the source text next to each line, numbered within its file.
+    pub fn table(&self, source: &Source) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{:>6} {:>12} {:>12}  source", "line", "instructions", "accesses");
+        for (line, counts) in self.lines.iter() {
+            let (position, text) = (source.position(*line), source.text(*line));
+            let _ = writeln!(out, "{:>6} {:>12} {:>12}  {}", position, counts.instructions, counts.accesses, text);
+        }
+
+        let _ = writeln!(out, "\n{:<16} {:>12} {:>12}", "label", "instructions", "accesses");
+        for (label, counts) in self.labels.iter() {
+            let _ = writeln!(out, "{:<16} {:>12} {:>12}", label, counts.instructions, counts.accesses);
+        }
+
+        let _ = writeln!(out, "\ntotal: {}", self.total);
+        out
+    }
+
+    // Lines are given as the file they are in and their number there.
+    pub fn json(&self, source: &Source) -> String {
+        let counts = |c: &Counts| format!("\"instructions\": {}, \"accesses\": {}", c.instructions, c.accesses);
+        let place = |line: usize| {
+            let (file, local) = source.locate(line);
+            format!("\"file\": {}, \"line\": {}", string(file), local)
+        };
+        let optional = |line: Option<usize>| line.map_or("\"file\": null, \"line\": null".to_string(), place);
+
+        let instructions: Vec<String> = self
+            .instructions
+            .iter()
+            .map(|i| {
+                format!(
+                    "    {{\"address\": {}, \"instruction\": \"{}\", {}, {}}}",
+                    i.address,
+                    escape(&i.text),
+                    optional(i.line),
+                    counts(&i.counts)
+                )
+            })
+            .collect();
+        let lines: Vec<String> =
+            self.lines.iter().map(|(line, c)| format!("    {{{}, {}}}", place(*line), counts(c))).collect();
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|(label, c)| format!("    {{\"label\": \"{}\", {}}}", escape(label), counts(c)))
+            .collect();
+
+        format!(
+            "{{\n  \"total\": {{{}}},\n  \"instructions\": [\n{}\n  ],\n  \"lines\": [\n{}\n  ],\n  \"labels\": [\n{}\n  ]\n}}\n",
+            counts(&self.total),
+            instructions.join(",\n"),
+            lines.join(",\n"),
+            labels.join(",\n")
+        )
+    }
+}
@@ -0,0 +1,249 @@
+// Redundant load/store elimination. A forward dataflow analysis over the control-flow graph works out,
+// at every instruction, which memory words are known to hold the same value as the accumulator and
+// which constant the accumulator holds. A load of such a word, or a store of the accumulator into one,
+// changes nothing and is removed.
+
+use std::collections::{BTreeSet, HashSet};
+
+use crate::cfg::{Cfg, EdgeKind};
+use crate::{Program, ReferenceType, SimpleInstruction};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct State {
+    // Words whose value equals the accumulator.
+    same: BTreeSet<String>,
+    // The accumulator's value, when it is a known constant.
+    value: Option<i16>,
+}
+
+impl State {
+    fn meet(&self, other: &State) -> State {
+        State {
+            same: self.same.intersection(&other.same).cloned().collect(),
+            value: if self.value == other.value { self.value } else { None },
+        }
+    }
+
+    fn forget(&mut self) {
+        self.same.clear();
+        self.value = None;
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Report {
+    pub loads: usize,
+    pub stores: usize,
+}
+
+struct Analysis {
+    // Constant words nothing stores into, so their value is the one they are declared with.
+    constants: HashSet<String>,
+}
+
+impl Analysis {
+    fn new(prog: &Program) -> Analysis {
+        let stored: HashSet<&str> = prog
+            .instructions
+            .iter()
+            .filter_map(|i| match i {
+                SimpleInstruction::Store(r) => Some(r.var.name.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        let constants = prog
+            .variables
+            .iter()
+            .filter(|v| v.constant && !v.name.ends_with("_addr") && !stored.contains(v.name.as_str()))
+            .map(|v| v.name.clone())
+            .collect();
+
+        Analysis { constants }
+    }
+
+    fn constant_value(&self, prog: &Program, name: &str) -> Option<i16> {
+        if self.constants.contains(name) {
+            prog.get_variable(name).map(|v| v.default_value)
+        }
+        else {
+            None
+        }
+    }
+
+    // Whether the instruction can go given the state before it, and the state after it.
+    fn transfer(&self, prog: &Program, instruction: &SimpleInstruction, state: &mut State) -> bool {
+        use SimpleInstruction as SI;
+        match instruction {
+            SI::Load(r) => match r.reference_type {
+                ReferenceType::Direct => {
+                    let value = self.constant_value(prog, &r.var.name);
+                    if state.same.contains(&r.var.name) || (value.is_some() && value == state.value) {
+                        return true;
+                    }
+                    state.same = BTreeSet::from([r.var.name.clone()]);
+                    state.value = value;
+                }
+                _ => state.forget(),
+            },
+            SI::Store(r) => match r.reference_type {
+                ReferenceType::Direct => {
+                    if state.same.contains(&r.var.name) {
+                        return true;
+                    }
+                    state.same.insert(r.var.name.clone());
+                }
+                // The pointer may alias any word, so only the accumulator itself is still known.
+                _ => state.same.clear(),
+            },
+            SI::Clear => {
+                if state.value == Some(0) {
+                    return true;
+                }
+                state.forget();
+                state.value = Some(0);
+            }
+            // A subroutine may change anything, and so may hand-written code.
+            SI::Add(_) | SI::Subt(_) | SI::Input | SI::Jns(_) | SI::Asm(_) => state.forget(),
+            SI::Output | SI::Skipcond(_) | SI::Jump(_) | SI::JumpI(_) | SI::Label(_) | SI::Word(_) | SI::Halt => {}
+        }
+        false
+    }
+}
+
+pub fn remove_redundant(prog: &mut Program) -> Report {
+    let cfg = Cfg::build(&prog.instructions);
+    let analysis = Analysis::new(prog);
+
+    // Blocks entered from outside the graph start knowing nothing: the entry point, subroutines, labels
+    // whose address is taken and labels asm code refers to.
+    let mut external = vec![false; cfg.blocks.len()];
+    if let Some(entry) = cfg.entry {
+        external[entry] = true;
+    }
+    let asm_targets: HashSet<&str> = prog.instructions.iter().filter_map(|i| i.asm_symbol()).collect();
+    for (k, block) in cfg.blocks.iter().enumerate() {
+        if let Some(label) = &block.label {
+            if prog.get_variable(&format!("{}_addr", label)).is_some() || asm_targets.contains(label.as_str()) {
+                external[k] = true;
+            }
+        }
+        for edge in block.successors.iter().filter(|e| e.kind == EdgeKind::Call) {
+            external[edge.to] = true;
+        }
+    }
+
+    // None stands for a block no path has reached yet.
+    let predecessors = cfg.predecessors();
+    let mut outs: Vec<Option<State>> = vec![None; cfg.blocks.len()];
+    let mut ins: Vec<Option<State>> = vec![None; cfg.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (k, block) in cfg.blocks.iter().enumerate() {
+            let input = if external[k] {
+                Some(State::default())
+            }
+            else {
+                predecessors[k]
+                    .iter()
+                    .filter_map(|&p| outs[p].as_ref())
+                    .fold(None, |acc: Option<State>, s| Some(acc.map_or(s.clone(), |a| a.meet(s))))
+            };
+
+            let Some(mut state) = input.clone() else { continue };
+            for instruction in prog.instructions[block.range.clone()].iter() {
+                analysis.transfer(prog, instruction, &mut state);
+            }
+
+            if outs[k].as_ref() != Some(&state) {
+                outs[k] = Some(state);
+                changed = true;
+            }
+            ins[k] = input;
+        }
+    }
+
+    let mut redundant = vec![false; prog.instructions.len()];
+    let mut report = Report::default();
+    for (k, block) in cfg.blocks.iter().enumerate() {
+        let Some(mut state) = ins[k].clone() else { continue };
+        for i in block.range.clone() {
+            let instruction = &prog.instructions[i];
+            // Removing the instruction after a skipcond would make it skip the next one instead.
+            let skipped = prog.skipped(i);
+            if analysis.transfer(prog, instruction, &mut state) && !skipped {
+                redundant[i] = true;
+                match instruction {
+                    SimpleInstruction::Store(_) => report.stores += 1,
+                    _ => report.loads += 1,
+                }
+            }
+        }
+    }
+
+    for i in (0..prog.instructions.len()).rev() {
+        if redundant[i] {
+            prog.splice(i..i + 1, Vec::new());
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Registry;
+    use crate::{front_end, ir, modules};
+
+    // The MARIE code left after the pass, with the counts it reports.
+    fn optimise(source: &str) -> (Vec<String>, usize, usize) {
+        let source = modules::load_str("test.txt", source).unwrap();
+        let (mut prog, code) = front_end(&source, &Registry::default(), false).unwrap();
+        ir::lower(&code, &mut prog).unwrap();
+        let report = remove_redundant(&mut prog);
+        let text = prog.instructions.iter().map(|i| i.to_native()).collect();
+        (text, report.loads, report.stores)
+    }
+
+    fn count(text: &[String], line: &str) -> usize {
+        text.iter().filter(|l| l.as_str() == line).count()
+    }
+
+    #[test]
+    fn loads_and_stores_of_the_accumulator_go() {
+        let (text, loads, stores) = optimise("var $x\nvar $y\n#main\n    load $x\n    store $y\n    store $y\n    load $y\n    output\n    halt\n");
+        assert_eq!((loads, stores), (1, 1));
+        assert_eq!(count(&text, "store y"), 1);
+        assert_eq!(count(&text, "load y"), 0);
+    }
+
+    // Going through a pointer reads or writes a word the analysis can't name, so what it knew about `x`
+    // goes with it.
+    #[test]
+    fn pointer_loads_forget_the_accumulator() {
+        let source = "var $x\nvar $p\n#main\n    $p = &x\n    load $x\n    load @p\n    load $x\n    output\n    halt\n";
+        let (text, loads, _) = optimise(source);
+        assert_eq!(loads, 0);
+        assert_eq!(count(&text, "load x"), 2);
+    }
+
+    #[test]
+    fn chained_pointer_loads_forget_the_accumulator() {
+        let source = "var $x\nvar $p\nvar $q\n#main\n    $p = &x\n    $q = &p\n    load $x\n    load @@q\n    load $x\n    output\n    halt\n";
+        let (text, loads, _) = optimise(source);
+        assert_eq!(loads, 0);
+        assert_eq!(count(&text, "load x"), 2);
+    }
+
+    // Whatever the pointer holds, a store through it is never known to be redundant.
+    #[test]
+    fn pointer_stores_are_kept() {
+        let source = "var $x\nvar $p\nvar $q\n#main\n    $p = &x\n    $q = &p\n    load $x\n    store @p\n    store @p\n    store @@q\n    store @@q\n    halt\n";
+        let (text, _, stores) = optimise(source);
+        assert_eq!(stores, 0);
+        assert_eq!(count(&text, "storei p"), 2);
+        assert_eq!(text.iter().filter(|l| l.contains("storei temp_addr")).count(), 2);
+    }
+}
@@ -0,0 +1,166 @@
+// The instructions the compiler knows. Each one is looked up by its mnemonic and turns the operands of
+// a statement into IR through the builder. The built-in ones are registered by `Registry::default`, and
+// code using the compiler as a library can add its own next to them, or replace them:
+//
+//     let mut registry = Registry::default();
+//     registry.register_fn("double", vec![vec![OperandKind::Value]], |operands, ir| {
+//         let x = ir.operand(&operands[0])?;
+//         ir.emit(Instr::Binary { dest: x.clone(), op: BinOp::Add, lhs: x.clone(), rhs: x });
+//         Ok(())
+//     });
+//
+// Operands are checked against the instruction's signatures before it is built, so `build` can take
+// their number and kinds for granted. `skipcond` is not an instruction here: it needs the statement
+// after it and is handled by the compiler itself.
+
+use std::collections::HashMap;
+
+use crate::ast::Operand;
+use crate::ir::Builder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    // $x, $5, @p, &x, @p[$i], &x[$i], @@p, $x[$r][$c] or @(...) around any of them
+    Value,
+    // #name
+    Label,
+    // 000, 400 or 800
+    Condition,
+    // ==, !=, <, <=, > or >=
+    Relation,
+}
+
+impl OperandKind {
+    pub fn matches(self, operand: &Operand) -> bool {
+        match self {
+            OperandKind::Value => matches!(
+                operand,
+                Operand::Number(_)
+                    | Operand::Var(_)
+                    | Operand::Pointer(_)
+                    | Operand::Address(_)
+                    | Operand::Index(..)
+                    | Operand::Element(..)
+                    | Operand::Indirect(..)
+                    | Operand::Cell(..)
+                    | Operand::Deref(_)
+            ),
+            OperandKind::Label => matches!(operand, Operand::Label(_)),
+            OperandKind::Condition => matches!(operand, Operand::Code(_)),
+            OperandKind::Relation => matches!(operand, Operand::Relation(_)),
+        }
+    }
+
+    fn placeholder(self) -> &'static str {
+        match self {
+            OperandKind::Value => "$value",
+            OperandKind::Label => "#label",
+            OperandKind::Condition => "000",
+            OperandKind::Relation => "==",
+        }
+    }
+}
+
+pub trait Instruction {
+    // The operand lists the instruction accepts.
+    fn signatures(&self) -> Vec<Vec<OperandKind>>;
+
+    // Emits the IR for one statement, whose operands match one of the signatures.
+    fn build(&self, operands: &[Operand], ir: &mut Builder) -> Result<(), String>;
+}
+
+type BuildFn = Box<dyn Fn(&[Operand], &mut Builder) -> Result<(), String>>;
+
+// An instruction made of a function, for `register_fn`.
+struct FnInstruction {
+    signatures: Vec<Vec<OperandKind>>,
+    build: BuildFn,
+}
+
+impl Instruction for FnInstruction {
+    fn signatures(&self) -> Vec<Vec<OperandKind>> {
+        self.signatures.clone()
+    }
+
+    fn build(&self, operands: &[Operand], ir: &mut Builder) -> Result<(), String> {
+        (self.build)(operands, ir)
+    }
+}
+
+pub struct Registry {
+    instructions: HashMap<String, Box<dyn Instruction>>,
+}
+
+impl Registry {
+    // A registry without any instructions, not even the built-in ones.
+    pub fn empty() -> Registry {
+        Registry { instructions: HashMap::new() }
+    }
+
+    // Adds an instruction, replacing one registered under the same mnemonic.
+    pub fn register(&mut self, mnemonic: &str, instruction: impl Instruction + 'static) {
+        self.instructions.insert(mnemonic.to_string(), Box::new(instruction));
+    }
+
+    pub fn register_fn<F>(&mut self, mnemonic: &str, signatures: Vec<Vec<OperandKind>>, build: F)
+    where
+        F: Fn(&[Operand], &mut Builder) -> Result<(), String> + 'static,
+    {
+        self.register(mnemonic, FnInstruction { signatures, build: Box::new(build) });
+    }
+
+    pub fn get(&self, mnemonic: &str) -> Option<&dyn Instruction> {
+        self.instructions.get(mnemonic).map(|i| i.as_ref())
+    }
+
+    pub fn mnemonics(&self) -> Vec<&str> {
+        let mut mnemonics: Vec<&str> = self.instructions.keys().map(String::as_str).collect();
+        mnemonics.sort();
+        mnemonics
+    }
+
+    // Checks a statement's operands against the instruction's signatures and builds it.
+    pub fn build(&self, mnemonic: &str, operands: &[Operand], ir: &mut Builder) -> Result<(), String> {
+        let instruction = self.get(mnemonic).ok_or(format!("unknown instruction `{}`", mnemonic))?;
+        let signatures = instruction.signatures();
+        let fits = |kinds: &Vec<OperandKind>| kinds.len() == operands.len() && kinds.iter().zip(operands).all(|(k, o)| k.matches(o));
+        if !signatures.iter().any(fits) {
+            return Err(format!("`{}` takes {}", mnemonic, usage(&signatures)));
+        }
+        instruction.build(operands, ir)
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut registry = Registry::empty();
+        crate::handlers::register(&mut registry);
+        registry
+    }
+}
+
+// `$value, $value` for one signature, `no operands` for an empty one. A relation goes between the
+// operands around it, without commas.
+fn form(kinds: &[OperandKind]) -> String {
+    if kinds.is_empty() {
+        return "no operands".to_string();
+    }
+    let mut form = String::new();
+    for (i, kind) in kinds.iter().enumerate() {
+        if i > 0 {
+            let relation = *kind == OperandKind::Relation || kinds[i - 1] == OperandKind::Relation;
+            form.push_str(if relation { " " } else { ", " });
+        }
+        form.push_str(kind.placeholder());
+    }
+    format!("`{}`", form)
+}
+
+fn usage(signatures: &[Vec<OperandKind>]) -> String {
+    let forms: Vec<String> = signatures.iter().map(|kinds| form(kinds)).collect();
+    match forms.split_last() {
+        None => "nothing it can be given".to_string(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+    }
+}
@@ -0,0 +1,311 @@
+// MARIE machine: 4096 words of 16-bit memory and the AC, PC, MAR, MBR and IR registers, executing the
+// instruction set one fetch-decode-execute cycle at a time.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::ir::ASSERT_LINE;
+use crate::native::{Image, NativeInstruction, MEMORY_SIZE};
+
+// Where input comes from and output goes to.
+pub trait Io {
+    fn input(&mut self) -> Result<i16, Fault>;
+    fn output(&mut self, value: i16);
+}
+
+// Decimal numbers read from stdin, separated by whitespace, and written one per line to stdout.
+#[derive(Default)]
+pub struct StdIo {
+    pending: Vec<String>,
+}
+
+impl Io for StdIo {
+    fn input(&mut self) -> Result<i16, Fault> {
+        while self.pending.is_empty() {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line).map_err(|e| Fault::Input(e.to_string()))? == 0 {
+                return Err(Fault::Input("end of input".to_string()));
+            }
+            self.pending = line.split_whitespace().rev().map(String::from).collect();
+        }
+
+        let token = self.pending.pop().unwrap();
+        token.parse().map_err(|_| Fault::Input(format!("`{}` is not a 16-bit number", token)))
+    }
+
+    fn output(&mut self, value: i16) {
+        println!("{}", value);
+        let _ = io::stdout().flush();
+    }
+}
+
+// Input given up front and output collected, for running programs without a terminal.
+#[derive(Debug, Default)]
+pub struct BufferIo {
+    pub input: VecDeque<i16>,
+    pub output: Vec<i16>,
+}
+
+impl Io for BufferIo {
+    fn input(&mut self) -> Result<i16, Fault> {
+        self.input.pop_front().ok_or_else(|| Fault::Input("end of input".to_string()))
+    }
+
+    fn output(&mut self, value: i16) {
+        self.output.push(value);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    Input(String),
+    IllegalInstruction { address: u16, word: u16 },
+    StepLimit(u64),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::Input(message) => write!(f, "input failed: {}", message),
+            Fault::IllegalInstruction { address, word } => {
+                write!(f, "illegal instruction {:04X} at address {:03X}", word, address)
+            }
+            Fault::StepLimit(limit) => write!(f, "no halt after {} instructions, is there an infinite loop?", limit),
+        }
+    }
+}
+
+pub struct Machine {
+    pub memory: Vec<u16>,
+    pub ac: i16,
+    pub pc: u16,
+    pub mar: u16,
+    pub mbr: i16,
+    pub ir: u16,
+    pub halted: bool,
+    pub steps: u64,
+    // Times the instruction at each address ran, and the memory accesses it made, the fetch included.
+    pub executed: Vec<u64>,
+    pub accesses: Vec<u64>,
+    current: usize,
+}
+
+impl Machine {
+    pub fn new(image: &Image) -> Machine {
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[..image.words.len()].copy_from_slice(&image.words);
+        Machine {
+            memory,
+            ac: 0,
+            pc: 0,
+            mar: 0,
+            mbr: 0,
+            ir: 0,
+            halted: false,
+            steps: 0,
+            executed: vec![0; MEMORY_SIZE],
+            accesses: vec![0; MEMORY_SIZE],
+            current: 0,
+        }
+    }
+
+    fn read(&mut self) {
+        self.accesses[self.current] += 1;
+        self.mbr = self.memory[self.mar as usize] as i16;
+    }
+
+    fn write(&mut self) {
+        self.accesses[self.current] += 1;
+        self.memory[self.mar as usize] = self.mbr as u16;
+    }
+
+    // Runs one instruction.
+    pub fn step(&mut self, io: &mut dyn Io) -> Result<(), Fault> {
+        use NativeInstruction::*;
+        let address = self.pc;
+        self.mar = self.pc;
+        self.ir = self.memory[self.mar as usize];
+        self.pc = (self.pc + 1) & 0xFFF;
+
+        let Some(instruction) = NativeInstruction::from_opcode(self.ir >> 12) else {
+            return Err(Fault::IllegalInstruction { address, word: self.ir });
+        };
+        let operand = self.ir & 0xFFF;
+        self.steps += 1;
+        self.current = address as usize;
+        self.executed[self.current] += 1;
+        self.accesses[self.current] += 1;
+
+        match instruction {
+            Jns => {
+                self.mbr = self.pc as i16;
+                self.mar = operand;
+                self.write();
+                self.mbr = operand as i16;
+                self.ac = self.mbr.wrapping_add(1);
+                self.pc = self.ac as u16 & 0xFFF;
+            }
+            Load => {
+                self.mar = operand;
+                self.read();
+                self.ac = self.mbr;
+            }
+            Store => {
+                self.mar = operand;
+                self.mbr = self.ac;
+                self.write();
+            }
+            Add | Subt => {
+                self.mar = operand;
+                self.read();
+                self.ac = if instruction == Add { self.ac.wrapping_add(self.mbr) } else { self.ac.wrapping_sub(self.mbr) };
+            }
+            Input => self.ac = io.input()?,
+            Output => io.output(self.ac),
+            Halt => self.halted = true,
+            // Bits 11 and 10 pick the condition: 00 is negative, 01 is zero and 10 is positive.
+            Skipcond => {
+                let skip = match operand >> 10 & 0b11 {
+                    0b00 => self.ac < 0,
+                    0b01 => self.ac == 0,
+                    0b10 => self.ac > 0,
+                    _ => false,
+                };
+                if skip {
+                    self.pc = (self.pc + 1) & 0xFFF;
+                }
+            }
+            Jump => self.pc = operand,
+            Clear => self.ac = 0,
+            Addi | Loadi => {
+                self.mar = operand;
+                self.read();
+                self.mar = self.mbr as u16 & 0xFFF;
+                self.read();
+                self.ac = if instruction == Addi { self.ac.wrapping_add(self.mbr) } else { self.mbr };
+            }
+            Jumpi => {
+                self.mar = operand;
+                self.read();
+                self.pc = self.mbr as u16 & 0xFFF;
+            }
+            Storei => {
+                self.mar = operand;
+                self.read();
+                self.mar = self.mbr as u16 & 0xFFF;
+                self.mbr = self.ac;
+                self.write();
+            }
+        }
+
+        Ok(())
+    }
+
+    // Runs until halt, giving up after `limit` instructions.
+    pub fn run(&mut self, io: &mut dyn Io, limit: u64) -> Result<(), Fault> {
+        while !self.halted {
+            if self.steps >= limit {
+                return Err(Fault::StepLimit(limit));
+            }
+            self.step(io)?;
+        }
+        Ok(())
+    }
+
+    // The source line of the assert that stopped the program, if one did.
+    pub fn failed_assertion(&self, image: &Image) -> Option<usize> {
+        let address = *image.symbols.get(ASSERT_LINE)?;
+        match self.memory[address as usize] {
+            0 => None,
+            line => Some(line as usize),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    fn image(source: &str) -> Image {
+        let source: Vec<String> = source.lines().map(String::from).collect();
+        assembler::assemble(&source).unwrap_or_else(|errors| panic!("{:?}", errors))
+    }
+
+    fn run(source: &str, input: &[i16]) -> (Machine, Vec<i16>) {
+        let mut machine = Machine::new(&image(source));
+        let mut io = BufferIo { input: input.iter().copied().collect(), output: Vec::new() };
+        machine.run(&mut io, 1000).unwrap();
+        (machine, io.output)
+    }
+
+    // Outputs 1 when the condition skips the jump over the output.
+    fn skips(condition: &str, value: i16) -> bool {
+        let program = format!("input\nskipcond {}\njump done\nload one\noutput\ndone, halt\none, dec 1", condition);
+        run(&program, &[value]).1 == [1]
+    }
+
+    #[test]
+    fn skipcond_conditions() {
+        for (condition, skipped) in [("000", [true, false, false]), ("400", [false, true, false]), ("800", [false, false, true])] {
+            for (value, skipped) in [-5, 0, 5].into_iter().zip(skipped) {
+                assert_eq!(skips(condition, value), skipped, "skipcond {} with {}", condition, value);
+            }
+        }
+        // Bits 11 and 10 both set is no condition at all.
+        assert!(!skips("0C00", 0));
+    }
+
+    // jns stores the return address in the subroutine's first word and jumpi goes back through it.
+    #[test]
+    fn jns_and_jumpi() {
+        let (machine, output) = run("jns sub\noutput\nhalt\nsub, hex 0\nload x\njumpi sub\nx, dec 9", &[]);
+        assert_eq!(output, [9]);
+        assert_eq!(machine.memory[3], 1);
+    }
+
+    #[test]
+    fn indirect_instructions() {
+        let program = "loadi p\naddi p\nstorei q\nload y\noutput\nhalt\np, hex 8\nq, hex 9\nx, dec 21\ny, dec 0";
+        let (machine, output) = run(program, &[]);
+        assert_eq!(output, [42]);
+        assert_eq!(machine.memory[9], 42);
+    }
+
+    #[test]
+    fn arithmetic_wraps() {
+        let (_, output) = run("load big\nadd big\noutput\nsubt big\nsubt big\nsubt big\noutput\nhalt\nbig, dec 32767", &[]);
+        assert_eq!(output, [-2, -32767]);
+    }
+
+    #[test]
+    fn illegal_instructions_fault() {
+        let mut machine = Machine::new(&image("clear\nhex F123"));
+        let fault = machine.run(&mut BufferIo::default(), 1000).unwrap_err();
+        assert_eq!(fault, Fault::IllegalInstruction { address: 1, word: 0xF123 });
+        assert_eq!(machine.steps, 1);
+    }
+
+    #[test]
+    fn running_out_of_input_faults() {
+        let mut machine = Machine::new(&image("input\nhalt"));
+        assert!(matches!(machine.run(&mut BufferIo::default(), 1000), Err(Fault::Input(_))));
+    }
+
+    #[test]
+    fn loops_stop_at_the_step_limit() {
+        let mut machine = Machine::new(&image("loop, jump loop"));
+        assert_eq!(machine.run(&mut BufferIo::default(), 50), Err(Fault::StepLimit(50)));
+        assert_eq!(machine.steps, 50);
+        assert!(!machine.halted);
+    }
+
+    #[test]
+    fn instructions_and_accesses_are_counted() {
+        let (machine, _) = run("load x\nstore x\nloadi p\nhalt\nx, dec 1\np, hex 4", &[]);
+        assert_eq!(machine.executed[..4], [1, 1, 1, 1]);
+        // The fetch, then one access for load and store and two for loadi.
+        assert_eq!(machine.accesses[..4], [2, 2, 3, 1]);
+    }
+}
@@ -0,0 +1,79 @@
+// JSON source map from memory addresses back to the program text. The language has one statement per
+// line, so a statement's columns are where the line's text starts and where it ends before any comment.
+// Lines from included files point into those files.
+
+use crate::json;
+use crate::modules::Source;
+use crate::native::Line;
+use crate::Program;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Position<'a> {
+    file: &'a str,
+    line: usize,
+    column: usize,
+    end_column: usize,
+}
+
+fn position(line: usize, source: &Source) -> Position<'_> {
+    let text = line.checked_sub(1).and_then(|i| source.lines.get(i)).map(String::as_str).unwrap_or_default();
+    let (file, line) = source.locate(line);
+    let code = text.split("//").next().unwrap_or_default().trim_end();
+    let start = code.len() - code.trim_start().len();
+    // Columns count characters from 1, the end is the column of the last one.
+    Position {
+        file,
+        line,
+        column: code[..start].chars().count() + 1,
+        end_column: code.chars().count().max(1),
+    }
+}
+
+fn position_json(position: Option<Position>) -> String {
+    match position {
+        Some(p) => format!(
+            "{{\"file\": {}, \"line\": {}, \"column\": {}, \"end_column\": {}}}",
+            json::string(p.file),
+            p.line,
+            p.column,
+            p.end_column
+        ),
+        None => "null".to_string(),
+    }
+}
+
+pub fn source_map(prog: &Program, lines: &[Line], source: &Source) -> String {
+    let addresses: Vec<String> = lines
+        .iter()
+        .enumerate()
+        .map(|(address, line)| {
+            format!(
+                "    {{\"address\": {}, \"assembly\": {}, \"source\": {}}}",
+                address,
+                json::string(&line.to_string()),
+                position_json(line.source.map(|l| position(l, source)))
+            )
+        })
+        .collect();
+
+    let variables: Vec<String> = prog
+        .variables
+        .iter()
+        .map(|var| {
+            format!(
+                "    {{\"name\": {}, \"address\": {}, \"constant\": {}, \"declaration\": {}}}",
+                json::string(&var.name),
+                var.address,
+                var.constant,
+                position_json(var.line.map(|l| position(l, source)))
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\n  \"version\": 1,\n  \"file\": {},\n  \"addresses\": [\n{}\n  ],\n  \"variables\": [\n{}\n  ]\n}}\n",
+        json::string(source.path()),
+        addresses.join(",\n"),
+        variables.join(",\n")
+    )
+}
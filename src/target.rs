@@ -0,0 +1,133 @@
+// Target profiles. The classic textbook MARIE has no addi, jumpi, loadi or storei, so on that target
+// every one the compiler generates is rewritten as self-modifying code: the pointer plus the opcode of
+// the direct instruction is stored into a word in the code, which then runs as that instruction. Asm
+// blocks are taken as written, so using one of them there is an error instead.
+
+use std::collections::BTreeSet;
+
+use crate::diagnostic::Diagnostic;
+use crate::native::{LineKind, NativeInstruction as NI, Operand};
+use crate::{Program, SimpleInstruction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    Classic,
+    #[default]
+    MarieJs,
+}
+
+impl Target {
+    pub fn from_name(s: &str) -> Option<Target> {
+        match s {
+            "classic" => Some(Target::Classic),
+            "mariejs" => Some(Target::MarieJs),
+            _ => None,
+        }
+    }
+
+    pub fn supports(self, instruction: NI) -> bool {
+        match self {
+            Target::Classic => !matches!(instruction, NI::Addi | NI::Jumpi | NI::Loadi | NI::Storei),
+            Target::MarieJs => true,
+        }
+    }
+}
+
+// Saves the accumulator while the instruction word is built.
+const SAVED_ACC: &str = "_smc_acc";
+
+// The direct instruction an indirect one turns into, and the word holding its opcode.
+fn opcode_word(instruction: NI) -> (&'static str, i16) {
+    match instruction {
+        NI::Storei => ("_op_store", 0x2000),
+        NI::Jumpi => ("_op_jump", 0x9000u16 as i16),
+        _ => ("_op_load", 0x1000),
+    }
+}
+
+struct Rewriter {
+    instructions: Vec<SimpleInstruction>,
+    lines: Vec<usize>,
+    line: usize,
+    count: usize,
+    words: BTreeSet<(&'static str, i16)>,
+}
+
+impl Rewriter {
+    fn emit(&mut self, kind: LineKind) {
+        self.instructions.push(SimpleInstruction::Asm(kind));
+        self.lines.push(self.line);
+    }
+
+    fn op(&mut self, instruction: NI, name: &str) {
+        self.emit(LineKind::Instruction(instruction, Some(Operand::Symbol(name.to_string()))));
+    }
+
+    // `i x` becomes: load x, add the opcode, store the sum into `_smcN`, then run `_smcN`. Only loadi
+    // leaves nothing of the accumulator to keep.
+    fn indirect(&mut self, instruction: NI, pointer: Operand) {
+        self.count += 1;
+        let slot = format!("_smc{}", self.count);
+        let (opcode, value) = opcode_word(instruction);
+        self.words.insert((opcode, value));
+        let keep = instruction != NI::Loadi;
+        if keep {
+            self.words.insert((SAVED_ACC, 0));
+            self.op(NI::Store, SAVED_ACC);
+        }
+
+        self.emit(LineKind::Instruction(NI::Load, Some(pointer)));
+        self.op(NI::Add, opcode);
+        self.op(NI::Store, &slot);
+        // Addi adds the saved accumulator to the word once it is loaded.
+        if keep && instruction != NI::Addi {
+            self.op(NI::Load, SAVED_ACC);
+        }
+        self.instructions.push(SimpleInstruction::Label(slot));
+        self.lines.push(self.line);
+        self.emit(LineKind::Hex(0));
+        if instruction == NI::Addi {
+            self.op(NI::Add, SAVED_ACC);
+        }
+    }
+}
+
+// Rewrites the instructions the target lacks, adding the data words the rewrites use.
+pub fn lower(prog: &mut Program, target: Target) -> Result<(), Vec<Diagnostic>> {
+    let unsupported = |kind: &LineKind| matches!(kind, LineKind::Instruction(i, _) if !target.supports(*i));
+    let mut rewriter = Rewriter { instructions: Vec::new(), lines: Vec::new(), line: 0, count: 0, words: BTreeSet::new() };
+    let mut errors = Vec::new();
+
+    for (instruction, &line) in prog.instructions.iter().zip(prog.lines.iter()) {
+        let expansion = instruction.native();
+        if !expansion.iter().any(unsupported) {
+            rewriter.instructions.push(instruction.clone());
+            rewriter.lines.push(line);
+            continue;
+        }
+        if let SimpleInstruction::Asm(LineKind::Instruction(i, _)) = instruction {
+            errors.push(Diagnostic::error(format!("`{}` is not in the classic MARIE instruction set", i)).at(line));
+            continue;
+        }
+
+        rewriter.line = line;
+        for kind in expansion {
+            match kind {
+                LineKind::Instruction(i, Some(pointer)) if !target.supports(i) => rewriter.indirect(i, pointer),
+                kind => rewriter.emit(kind),
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    prog.instructions = rewriter.instructions;
+    prog.lines = rewriter.lines;
+    for (name, value) in rewriter.words {
+        if prog.get_variable(name).is_none() {
+            prog.add_variable(name, value, name != SAVED_ACC);
+        }
+    }
+    Ok(())
+}
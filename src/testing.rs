@@ -0,0 +1,120 @@
+// Golden-output tests. Every program in a directory is compiled and run on the simulator; its input
+// comes from `name.in` or `// input:` comments and its output is checked against `name.out` or
+// `// expect:` comments. A program with no expectations only has to compile and halt. Only the source
+// files directly in the directory are programs, so the modules they include can go in a subdirectory
+// and notes such as a README can sit next to them.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use crate::{modules, native};
+use crate::passes::PassManager;
+use crate::registry::Registry;
+use crate::simulator::{BufferIo, Machine};
+use crate::target::Target;
+use crate::{assertion_message, back_end, front_end, read_file};
+
+// How every program in the directory is compiled and run.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub passes: PassManager,
+    pub release: bool,
+    pub target: Target,
+    pub step_limit: u64,
+}
+
+// A program's file name, with why it failed when it did.
+pub type Outcome = (String, Result<(), String>);
+
+// Programs are `.src` files, or `.txt` like sample.txt.
+const SOURCE_EXTENSIONS: [&str; 2] = ["src", "txt"];
+
+fn numbers(text: &str) -> Result<Vec<i16>, String> {
+    text.split_whitespace()
+        .map(|token| token.parse::<i16>().map_err(|_| format!("`{}` is not a 16-bit number", token)))
+        .collect()
+}
+
+// Values from the companion file when there is one, otherwise from the `// <key>:` comments.
+fn values(path: &Path, extension: &str, key: &str, source: &[String]) -> Result<Option<Vec<i16>>, String> {
+    let companion = path.with_extension(extension);
+    if companion.is_file() {
+        let lines = read_file(&companion.to_string_lossy()).map_err(|e| format!("can't read {}: {}", companion.display(), e))?;
+        return numbers(&lines.join("\n")).map(Some);
+    }
+
+    let marker = format!("// {}:", key);
+    let comments: Vec<&str> = source
+        .iter()
+        .filter_map(|line| line.find(&marker).map(|i| &line[i + marker.len()..]))
+        .collect();
+    if comments.is_empty() {
+        return Ok(None);
+    }
+    numbers(&comments.join(" ")).map(Some)
+}
+
+fn diff(expected: &[i16], actual: &[i16]) -> String {
+    let mut out = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => out.push_str(&format!("      {}\n", a)),
+            (e, a) => {
+                if let Some(e) = e {
+                    out.push_str(&format!("    - {}\n", e));
+                }
+                if let Some(a) = a {
+                    out.push_str(&format!("    + {}\n", a));
+                }
+            }
+        }
+    }
+    out
+}
+
+// Runs one program, returning why it failed.
+fn check(path: &Path, config: &Config) -> Result<(), String> {
+    let report = |errors: Vec<crate::Diagnostic>| errors.iter().map(|e| format!("    {}\n", e)).collect::<String>();
+    let loaded = modules::load(&path.to_string_lossy()).map_err(|errors| format!("doesn't compile\n{}", report(errors)))?;
+    let source = loaded.main();
+
+    let (mut prog, code) = front_end(&loaded, &Registry::default(), config.release).map_err(|errors| format!("doesn't compile\n{}", report(errors)))?;
+    back_end(&code, &mut prog, &config.passes, config.target)
+        .map_err(|errors| format!("doesn't compile\n{}", report(errors.into_iter().map(|e| loaded.place(e)).collect())))?;
+    let image = native::assemble(&prog.native()).map_err(|errors| format!("doesn't assemble\n{}", report(errors)))?;
+
+    let input = values(path, "in", "input", source)?.unwrap_or_default();
+    let expected = values(path, "out", "expect", source)?;
+
+    let mut io = BufferIo { input: VecDeque::from(input), output: Vec::new() };
+    let mut machine = Machine::new(&image);
+    machine.run(&mut io, config.step_limit).map_err(|fault| format!("{}\n{}", fault, diff(expected.as_deref().unwrap_or_default(), &io.output)))?;
+
+    if let Some(line) = machine.failed_assertion(&image) {
+        return Err(loaded.place(crate::Diagnostic::error(assertion_message(line, &loaded.lines)).at(line)).to_string());
+    }
+
+    match expected {
+        Some(expected) if expected != io.output => Err(format!("wrong output\n{}", diff(&expected, &io.output))),
+        _ => Ok(()),
+    }
+}
+
+// Runs every program in the directory, in file name order.
+pub fn run_directory(dir: &str, config: &Config) -> Result<Vec<Outcome>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("can't read {}: {}", dir, e))?;
+    let mut programs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| path.extension().and_then(|e| e.to_str()).is_some_and(|e| SOURCE_EXTENSIONS.contains(&e)))
+        .collect();
+    programs.sort();
+
+    Ok(programs
+        .iter()
+        .map(|path| {
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            (name, check(path, config))
+        })
+        .collect())
+}
@@ -0,0 +1,126 @@
+// Usage analysis over the generated instructions. Every `$N` literal and `&x` in the file gets a data word
+// up front, so anything no instruction ended up referring to is dropped before layout.
+
+use std::collections::HashSet;
+
+use crate::{Program, ReferenceType, SimpleInstruction};
+
+// Names of all data words some instruction reads or writes.
+pub fn referenced(instructions: &[SimpleInstruction]) -> HashSet<String> {
+    use SimpleInstruction as SI;
+    let mut used = HashSet::new();
+
+    for instruction in instructions.iter() {
+        if let SI::Add(r) | SI::Subt(r) | SI::Store(r) | SI::Load(r) = instruction {
+            match &r.reference_type {
+                ReferenceType::Element(off) => {
+                    used.insert(off.name.clone());
+                }
+                ReferenceType::Offset(off) => {
+                    used.insert(off.name.clone());
+                    used.extend(["temp_acc", "temp_addr", "temp_val"].map(String::from));
+                }
+                ReferenceType::Grid { row, column, .. } => {
+                    used.extend([row.name.clone(), column.name.clone()]);
+                    used.extend(["temp_acc", "temp_addr", "temp_val"].map(String::from));
+                }
+                ReferenceType::Indirect(_) => {
+                    used.extend(["temp_acc", "temp_addr", "temp_val"].map(String::from));
+                }
+                ReferenceType::Pointer if matches!(instruction, SI::Subt(_)) => {
+                    used.extend(["temp_acc", "temp_val"].map(String::from));
+                }
+                ReferenceType::Direct | ReferenceType::Pointer => {}
+            }
+            used.insert(r.var.name.clone());
+        }
+        if let Some(name) = instruction.asm_symbol() {
+            used.insert(name.to_string());
+        }
+    }
+
+    // A word whose address is taken has to stay, it can be reached through the pointer.
+    let pointed_to: Vec<String> = used
+        .iter()
+        .filter_map(|name| name.strip_suffix("_addr"))
+        .map(|name| name.to_string())
+        .collect();
+    used.extend(pointed_to);
+
+    used
+}
+
+// Removes unreferenced words from the data section and returns their names. `&x[$i]` can reach any
+// word declared after `x`, so those all stay.
+pub fn remove_unused(prog: &mut Program) -> Vec<String> {
+    let used = referenced(&prog.instructions);
+    let indexed: HashSet<String> = prog
+        .instructions
+        .iter()
+        .filter_map(|i| match i {
+            SimpleInstruction::Add(r) | SimpleInstruction::Subt(r) | SimpleInstruction::Load(r) => match r.reference_type {
+                ReferenceType::Element(_) => r.var.name.strip_suffix("_addr").map(String::from),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    let first = prog.variables.iter().position(|v| indexed.contains(&v.name)).unwrap_or(prog.variables.len());
+    let mut dropped = Vec::new();
+
+    let mut k = 0;
+    prog.variables.retain(|v| {
+        k += 1;
+        let keep = used.contains(&v.name) || (k > first && v.line.is_some());
+        if !keep {
+            dropped.push(v.name.clone());
+        }
+        keep
+    });
+
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::Registry;
+    use crate::{dce, front_end, ir, modules};
+
+    // The words dropped from a program whose main file is `main.txt` next to the given files, which are
+    // written to a directory of the test's own.
+    fn dropped(test: &str, main: &str, files: &[(&str, &str)]) -> Vec<String> {
+        let dir = std::env::temp_dir().join(format!("marie-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files.iter() {
+            std::fs::write(dir.join(name), text).unwrap();
+        }
+        let source = modules::load_str(dir.join("main.txt").to_str().unwrap(), main);
+        let _ = std::fs::remove_dir_all(&dir);
+        let source = source.unwrap();
+        let (mut prog, code) = front_end(&source, &Registry::default(), false).unwrap();
+        ir::lower(&code, &mut prog).unwrap();
+        // The code before `main` that sets declared values is unreachable, dce goes first as it does in
+        // the pass pipeline.
+        dce::remove_unreachable(&mut prog);
+        remove_unused(&mut prog)
+    }
+
+    #[test]
+    fn unused_words_go() {
+        let dropped = dropped("unused", "var $x = $1\nvar $y = $2\n#main\n    output $x\n    halt\n", &[]);
+        assert!(dropped.contains(&"y".to_string()), "{:?}", dropped);
+        assert!(!dropped.contains(&"x".to_string()));
+    }
+
+    // A module's public words are used by their qualified names in the file that includes it.
+    #[test]
+    fn public_words_used_from_another_file_stay() {
+        let lib = "module lib\npub var $used = $3\npub var $pointed = $4\npub var $unused = $5\nvar $own = $6\n";
+        let main = "include \"lib.txt\"\nvar $p\n#main\n    output $lib.used\n    $p = &lib.pointed\n    halt\n";
+        let dropped = dropped("public", main, &[("lib.txt", lib)]);
+        assert!(!dropped.iter().any(|d| d == "lib__used" || d == "lib__pointed"), "{:?}", dropped);
+        assert!(dropped.contains(&"lib__unused".to_string()), "{:?}", dropped);
+        assert!(dropped.contains(&"lib__own".to_string()), "{:?}", dropped);
+    }
+}
@@ -0,0 +1,86 @@
+// The library compile API, used the way another crate would use it.
+
+use marie_compiler::diagnostic::Severity;
+use marie_compiler::ir::{BinOp, Instr};
+use marie_compiler::registry::OperandKind;
+use marie_compiler::{compile, CompileOptions, Level, Target};
+
+const DOUBLE: &str = "\
+var $x = $4
+#main
+    add $x, $x
+    output $x
+    halt
+";
+
+#[test]
+fn compiles_to_assembly_and_an_image() {
+    let compiled = compile(DOUBLE, &CompileOptions::default()).unwrap();
+
+    assert!(compiled.assembly().contains("output"));
+    assert_eq!(compiled.lines().len(), compiled.image().words.len());
+    // Address 0 jumps to main.
+    let main = compiled.symbols()["main"];
+    assert_eq!(compiled.image().words[0], 0x9000 | main);
+    assert!(compiled.warnings().iter().all(|w| w.severity == Severity::Warning));
+
+    let program = compiled.program();
+    assert_eq!(program.instructions().len(), program.source_lines().len());
+    assert!(program.instructions().iter().any(|i| i == "halt"));
+    assert!(program.get_variable("x").is_some_and(|x| x.default_value == 4));
+}
+
+#[test]
+fn options_change_the_result() {
+    let mut options = CompileOptions::default();
+    options.level = Level::O0;
+    options.target = Target::Classic;
+    let unoptimised = compile("var $p\n#main\n    output @p\n    halt\n", &options).unwrap();
+    // Classic MARIE has no loadi.
+    assert!(!unoptimised.assembly().contains("loadi"));
+
+    options.disabled_passes = vec!["inline".to_string()];
+    let errors = compile(DOUBLE, &options).err().unwrap();
+    assert!(errors[0].message.contains("unknown pass `inline`"));
+}
+
+#[test]
+fn errors_come_back_with_their_lines() {
+    let errors = compile("#main\n    frobnicate $x\n    halt\n", &CompileOptions::default()).err().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].severity, Severity::Error);
+    assert_eq!(errors[0].line, Some(2));
+}
+
+#[test]
+fn registered_instructions_can_be_used() {
+    let mut options = CompileOptions::default();
+    options.registry.register_fn("double", vec![vec![OperandKind::Value]], |operands, ir| {
+        let x = ir.operand(&operands[0])?;
+        ir.emit(Instr::Binary { dest: x.clone(), op: BinOp::Add, lhs: x.clone(), rhs: x });
+        Ok(())
+    });
+    let compiled = compile("var $x = $4\n#main\n    double $x\n    output $x\n    halt\n", &options).unwrap();
+    assert!(compiled.assembly().contains("add x"));
+}
+
+#[test]
+fn release_asserts_compile_to_nothing() {
+    let program = |assert: &str| format!("var $arr[$3]\nvar $i = $1\n#main\n{}    output $i\n    halt\n", assert);
+    let mut options = CompileOptions::default();
+    options.release = true;
+    options.level = Level::O0;
+    let with = compile(&program("    assert @(&arr[$i]) == $0\n"), &options).unwrap();
+    let without = compile(&program(""), &options).unwrap();
+    assert_eq!(with.assembly(), without.assembly());
+}
+
+#[test]
+fn the_assert_routine_has_no_source_line() {
+    let compiled = compile("var $x = $1\n#main\n    assert $x == $1\n    output $x\n    halt\n", &CompileOptions::default()).unwrap();
+    let lines = compiled.lines();
+    let start = lines.iter().position(|l| l.label.as_deref() == Some("_assert_fail")).unwrap();
+    // The routine comes after all the code written in the source.
+    assert!(lines[start..].iter().all(|l| l.source.is_none()));
+    assert!(lines.iter().any(|l| l.source == Some(3)));
+}
@@ -0,0 +1,74 @@
+// Whole programs compiled through the library and run on the simulator.
+
+use marie_compiler::simulator::{BufferIo, Machine};
+use marie_compiler::{compile, CompileOptions, Level};
+
+fn run(source: &str, level: Level) -> Vec<i16> {
+    let mut options = CompileOptions::default();
+    options.level = level;
+    let compiled = compile(source, &options).unwrap_or_else(|errors| panic!("{:?}", errors));
+    let mut io = BufferIo::default();
+    Machine::new(compiled.image()).run(&mut io, 10_000).unwrap();
+    io.output
+}
+
+// The labelled jump is what the skipcond skips, so the output after it is reachable.
+#[test]
+fn asm_skipcond_before_a_label() {
+    let source = "\
+var $x
+#main
+asm {
+    load $x
+    skipcond 400
+back, jump out
+    load $1
+    output
+out, halt
+}
+";
+    for level in [Level::O0, Level::O2, Level::Os] {
+        assert_eq!(run(source, level), [1], "{:?}", level);
+    }
+}
+
+// Two operand subt keeps its original meaning, the destination is taken from the source.
+#[test]
+fn subt_takes_the_destination_from_the_source() {
+    let source = "\
+var $d = $3
+var $s = $10
+#main
+    subt $d, $s
+    output $d
+    halt
+";
+    for level in [Level::O0, Level::O2, Level::Os] {
+        assert_eq!(run(source, level), [7], "{:?}", level);
+    }
+}
+
+// `#main`, `#a` and `#b` follow each other, so jumps join them and move `target` on by two words.
+#[test]
+fn label_addresses_count_joined_labels() {
+    let source = "\
+var $p
+#main
+#a
+#b
+    $p = &target
+    output $p
+asm {
+    jumpi $p
+}
+    halt
+#target
+    output $7
+    halt
+";
+    let compiled = compile(source, &CompileOptions::default()).unwrap();
+    let target = compiled.symbols()["target"] as i16;
+    let mut io = BufferIo::default();
+    Machine::new(compiled.image()).run(&mut io, 10_000).unwrap();
+    assert_eq!(io.output, [target, 7]);
+}